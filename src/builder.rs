@@ -0,0 +1,541 @@
+//! Building load tests up in code rather than from the command line's options, and following them whilst they run
+//! through a stream of every request's result, progress callbacks and cancellation.
+
+use std::{
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Method, Url,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+use crate::{args::*, runner::*, stats::*};
+
+/// Represents a callback that is given the progress of a run at a fixed interval whilst it's in progress.
+pub(crate) type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Represents what a load test built in code is followed by whilst it runs, besides what its options ask for.
+#[derive(Clone, Default)]
+pub(crate) struct RunHooks {
+    /// Where every request's result is sent as it completes.
+    pub(crate) events: Option<UnboundedSender<RequestEvent>>,
+    /// The callback that's given the run's progress, and how often it is.
+    pub(crate) progress: Option<(Duration, ProgressCallback)>,
+    /// The token that stops the run early once it's cancelled.
+    pub(crate) cancellation: Option<CancellationToken>,
+}
+
+/// Represents the result of one of a run's requests, as it's sent to the [`RequestEvents`] stream once it completes.
+#[derive(Debug, Clone)]
+pub struct RequestEvent {
+    pub method: String,
+    pub url: String,
+    /// The response's status code, if there was a response.
+    pub status: Option<u16>,
+    pub success: bool,
+    /// How long the request took, if it got a response.
+    pub response_time: Option<Duration>,
+    /// Why the request failed, if it didn't get a response or the response didn't pass its checks.
+    pub error: Option<String>,
+}
+
+/// Represents how far a run has got, as it's given to the callback passed to [`LoadTestBuilder::on_progress`].
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// The number of requests that have completed, whether they were successful or not.
+    pub completed: usize,
+    pub failed: usize,
+    pub in_flight: usize,
+    pub elapsed: Duration,
+}
+
+/// The response time statistics that a threshold can be set for with [`LoadTestBuilder::latency_threshold`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyStatistic {
+    Min,
+    Avg,
+    Max,
+    P50,
+    P75,
+    P90,
+    P99,
+}
+
+/// A stream of the result of every one of a run's requests as it completes, which ends once the run has finished and
+/// the [`LoadTest`] it's following has been dropped.
+pub struct RequestEvents(UnboundedReceiver<RequestEvent>);
+
+/// Builds a load test up in code and runs it. Its setters are typed, and are checked when the load test is built, with
+/// the command line's other options available through [`LoadTestBuilder::args`].
+#[derive(Default)]
+pub struct LoadTestBuilder {
+    url: Option<String>,
+    method: Option<Method>,
+    headers: Vec<(String, String)>,
+    body: Option<Bytes>,
+    count: Option<usize>,
+    concurrency: Option<usize>,
+    virtual_users: Option<usize>,
+    rate: Option<f64>,
+    duration: Option<Duration>,
+    timeout: Option<Duration>,
+    retries: Option<usize>,
+    expect_status: Vec<u16>,
+    latency_thresholds: Vec<(LatencyStatistic, Duration)>,
+    max_error_rate: Option<f64>,
+    args: Vec<String>,
+    hooks: RunHooks,
+}
+
+impl LoadTestBuilder {
+    /// Starts building a load test with none of its options given yet.
+    pub fn new() -> LoadTestBuilder {
+        LoadTestBuilder::default()
+    }
+
+    /// The URL to load test, as with `--url`, which can contain placeholders such as `{{seq}}`.
+    pub fn url(mut self, url: impl Into<String>) -> LoadTestBuilder {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// The HTTP method to send the requests with, as with `--method`.
+    pub fn method(mut self, method: Method) -> LoadTestBuilder {
+        self.method = Some(method);
+        self
+    }
+
+    /// A header to send with every request, as with `--header`.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> LoadTestBuilder {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The body to send with every request, as it is (unlike `--body`, one starting with `@` isn't read from a file).
+    pub fn body(mut self, body: impl Into<Bytes>) -> LoadTestBuilder {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// The number of requests to send, as with `--count`.
+    pub fn count(mut self, count: usize) -> LoadTestBuilder {
+        self.count = Some(count);
+        self
+    }
+
+    /// The most requests to have in flight at once, as with `--concurrency`.
+    pub fn concurrency(mut self, concurrency: usize) -> LoadTestBuilder {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    /// The number of virtual users to run, as with `--vus`.
+    pub fn virtual_users(mut self, virtual_users: usize) -> LoadTestBuilder {
+        self.virtual_users = Some(virtual_users);
+        self
+    }
+
+    /// The number of requests to send each second, as with `--rate`.
+    pub fn rate(mut self, rate: f64) -> LoadTestBuilder {
+        self.rate = Some(rate);
+        self
+    }
+
+    /// How long to keep sending requests for, as with `--duration`, to the millisecond.
+    pub fn duration(mut self, duration: Duration) -> LoadTestBuilder {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// How long each request can take before it's given up on, as with `--timeout`, to the millisecond.
+    pub fn timeout(mut self, timeout: Duration) -> LoadTestBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The number of times to retry a request that fails to get a response at all, as with `--retries`.
+    pub fn retries(mut self, retries: usize) -> LoadTestBuilder {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// A status code that counts as successful rather than any 2xx, as with `--expect-status`, which can be given more
+    /// than once.
+    pub fn expect_status(mut self, status: u16) -> LoadTestBuilder {
+        self.expect_status.push(status);
+        self
+    }
+
+    /// The longest that a response time statistic can be for the run to pass, as with `--fail-if-p99` and the others,
+    /// to the millisecond, with [`RunResults::thresholds_passed`] telling whether it did.
+    pub fn latency_threshold(
+        mut self,
+        statistic: LatencyStatistic,
+        threshold: Duration,
+    ) -> LoadTestBuilder {
+        self.latency_thresholds.push((statistic, threshold));
+        self
+    }
+
+    /// The percentage of requests that can fail for the run to pass, as with `--max-error-rate`, with
+    /// [`RunResults::thresholds_passed`] telling whether it did.
+    pub fn max_error_rate(mut self, percentage: f64) -> LoadTestBuilder {
+        self.max_error_rate = Some(percentage);
+        self
+    }
+
+    /// Any of the other options that the command line takes, as they'd be given on it, e.g. `["--http2"]`.
+    pub fn args<S: Into<String>>(mut self, args: impl IntoIterator<Item = S>) -> LoadTestBuilder {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Calls the callback with the run's progress every interval whilst it's in progress.
+    pub fn on_progress(
+        mut self,
+        interval: Duration,
+        callback: impl Fn(&Progress) + Send + Sync + 'static,
+    ) -> LoadTestBuilder {
+        self.hooks.progress = Some((interval, Arc::new(callback)));
+        self
+    }
+
+    /// Stops the run early once the token is cancelled, waiting for its requests in flight as Ctrl+C does on the
+    /// command line, with [`RunResults::cancelled`] telling whether it was.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> LoadTestBuilder {
+        self.hooks.cancellation = Some(token);
+        self
+    }
+
+    /// Gets a stream of the result of every one of the run's requests as it completes, replacing any stream that was
+    /// got before.
+    pub fn events(&mut self) -> RequestEvents {
+        let (sender, receiver) = unbounded_channel();
+        self.hooks.events = Some(sender);
+        RequestEvents(receiver)
+    }
+
+    /// Checks the options and sets up the load test's clients, as with [`TestConfig::from_args`] and
+    /// [`LoadTest::new`], giving what's wrong with them if they aren't valid.
+    pub async fn build(self) -> Result<LoadTest, String> {
+        let mut args = self.get_args()?;
+        let given_args = self.args.join(" ");
+        args.extend(self.args);
+
+        let config = TestConfig::parse_with_body(
+            std::iter::once(String::from("smashit")).chain(args).collect(),
+            self.body,
+        )
+        .ok_or_else(|| match (self.url.is_some(), given_args.is_empty()) {
+            (false, true) => String::from(
+                "The load test needs a URL, or another source of requests (such as --scenario) in its args",
+            ),
+            (_, true) => String::from("The load test's options can't be given together"),
+            (_, false) => format!(
+                "The load test's args ({0}) aren't valid, or can't be given with its other options",
+                given_args
+            ),
+        })?;
+
+        let mut load_test = LoadTest::new(config).await?;
+        load_test.hooks = self.hooks;
+        Ok(load_test)
+    }
+
+    /// Builds the load test and runs it, giving the results once every request has completed.
+    pub async fn run(self) -> Result<RunResults, String> {
+        self.build().await?.run().await
+    }
+
+    /// Checks each of the typed options, giving them as the command line's options.
+    fn get_args(&self) -> Result<Vec<String>, String> {
+        let mut args = vec![];
+
+        if let Some(url) = &self.url {
+            // A URL with placeholders in it (e.g. in its host) can only be checked once they've been filled in.
+            let is_valid = if url.contains("{{") {
+                let scheme = url.split_once("://").map(|(s, _)| s.to_ascii_lowercase());
+                matches!(scheme.as_deref(), Some("http" | "https"))
+            } else {
+                Url::parse(url).is_ok_and(|u| matches!(u.scheme(), "http" | "https"))
+            };
+            if !is_valid {
+                return Err(format!("The URL {0} isn't a valid HTTP or HTTPS URL", url));
+            }
+            args.extend([String::from("-u"), url.clone()]);
+        }
+
+        if let Some(method) = &self.method {
+            args.extend([String::from("-m"), method.to_string()]);
+        }
+
+        for (name, value) in &self.headers {
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("The header name {0} isn't valid", name));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                return Err(format!("The value of the {0} header isn't valid", name));
+            }
+            args.extend([String::from("-h"), format!("{0}={1}", name, value)]);
+        }
+
+        if let Some(count) = self.count {
+            if count == 0 || count > i32::MAX as usize {
+                return Err(format!("The count must be from 1 to {0}", i32::MAX));
+            }
+            args.extend([String::from("-c"), count.to_string()]);
+        }
+
+        for (option, name, value) in [
+            ("-p", "concurrency", self.concurrency),
+            ("--vus", "number of virtual users", self.virtual_users),
+        ] {
+            match value {
+                Some(0) => return Err(format!("The {0} must be at least 1", name)),
+                Some(value) => args.extend([option.to_owned(), value.to_string()]),
+                None => {}
+            }
+        }
+
+        if let Some(rate) = self.rate {
+            if !rate.is_finite() || rate <= 0.0 {
+                return Err(format!(
+                    "The rate of {0} requests a second must be more than 0",
+                    rate
+                ));
+            }
+            args.extend([String::from("-r"), rate.to_string()]);
+        }
+
+        let durations = [
+            ("-d", String::from("duration"), self.duration),
+            ("--timeout", String::from("timeout"), self.timeout),
+        ];
+        let thresholds = self
+            .latency_thresholds
+            .iter()
+            .map(|(statistic, threshold)| {
+                let (option, name) = match statistic {
+                    LatencyStatistic::Min => ("--fail-if-min", "min"),
+                    LatencyStatistic::Avg => ("--fail-if-avg", "avg"),
+                    LatencyStatistic::Max => ("--fail-if-max", "max"),
+                    LatencyStatistic::P50 => ("--fail-if-p50", "p50"),
+                    LatencyStatistic::P75 => ("--fail-if-p75", "p75"),
+                    LatencyStatistic::P90 => ("--fail-if-p90", "p90"),
+                    LatencyStatistic::P99 => ("--fail-if-p99", "p99"),
+                };
+                (option, format!("{0} threshold", name), Some(*threshold))
+            });
+        for (option, name, value) in durations.into_iter().chain(thresholds) {
+            if let Some(value) = value {
+                if value < Duration::from_millis(1) {
+                    return Err(format!("The {0} must be at least a millisecond", name));
+                }
+                if value > MAX_DURATION {
+                    return Err(format!("The {0} can't be longer than a year", name));
+                }
+                args.extend([option.to_owned(), format!("{0}ms", value.as_millis())]);
+            }
+        }
+
+        if let Some(retries) = self.retries {
+            args.extend([String::from("--retries"), retries.to_string()]);
+        }
+
+        for status in &self.expect_status {
+            if !(100..=599).contains(status) {
+                return Err(format!("The status code {0} isn't valid", status));
+            }
+            args.extend([String::from("--expect-status"), status.to_string()]);
+        }
+
+        if let Some(max_error_rate) = self.max_error_rate {
+            if !(0.0..=100.0).contains(&max_error_rate) {
+                return Err(format!(
+                    "The maximum error rate of {0}% must be from 0 to 100%",
+                    max_error_rate
+                ));
+            }
+            args.extend([String::from("--max-error-rate"), max_error_rate.to_string()]);
+        }
+
+        Ok(args)
+    }
+}
+
+impl Stream for RequestEvents {
+    type Item = RequestEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<RequestEvent>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+impl RequestEvent {
+    /// Gets the event for a request's result.
+    pub(crate) fn from_result(result: &ResponseStatistics) -> RequestEvent {
+        RequestEvent {
+            method: result.method.to_string(),
+            url: result.url.clone(),
+            status: result.status_code.map(|s| s.as_u16()),
+            success: result.is_success,
+            response_time: result.response_time,
+            error: result.error.clone(),
+        }
+    }
+}
+
+/// Gives the run's progress to the callback every interval for the rest of the run.
+pub(crate) async fn call_progress_callback(
+    state: Arc<RunState>,
+    interval: Duration,
+    callback: ProgressCallback,
+) {
+    // Intervals have to be longer than nothing, and the first tick is straight away when there's no progress yet.
+    let mut interval = tokio::time::interval(interval.max(Duration::from_millis(1)));
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        callback(&Progress {
+            completed: state.completed.load(Ordering::SeqCst),
+            failed: state.failed.load(Ordering::SeqCst),
+            in_flight: state.in_flight.load(Ordering::SeqCst),
+            elapsed: state.started.elapsed(),
+        });
+    }
+}
+
+/// Stops the run early once the token is cancelled.
+pub(crate) async fn stop_on_cancellation(state: Arc<RunState>, token: CancellationToken) {
+    token.cancelled().await;
+    state.stop(
+        StopReason::Cancelled,
+        String::from("Stopped early by its cancellation token"),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the load test, giving the error that it couldn't be built with.
+    async fn get_build_error(builder: LoadTestBuilder) -> String {
+        match builder.build().await {
+            Ok(_) => panic!("the load test was built"),
+            Err(e) => e,
+        }
+    }
+
+    #[tokio::test]
+    async fn build_describes_what_is_wrong_with_the_typed_options() {
+        let builder = || LoadTestBuilder::new().url("http://localhost:8080");
+
+        assert_eq!(
+            get_build_error(LoadTestBuilder::new().url("localhost:8080")).await,
+            "The URL localhost:8080 isn't a valid HTTP or HTTPS URL"
+        );
+        assert_eq!(
+            get_build_error(builder().count(0)).await,
+            format!("The count must be from 1 to {0}", i32::MAX)
+        );
+        assert_eq!(
+            get_build_error(builder().concurrency(0)).await,
+            "The concurrency must be at least 1"
+        );
+        assert_eq!(
+            get_build_error(builder().rate(f64::NAN)).await,
+            "The rate of NaN requests a second must be more than 0"
+        );
+        assert_eq!(
+            get_build_error(builder().duration(Duration::from_micros(10))).await,
+            "The duration must be at least a millisecond"
+        );
+        assert_eq!(
+            get_build_error(builder().timeout(Duration::MAX)).await,
+            "The timeout can't be longer than a year"
+        );
+        assert_eq!(
+            get_build_error(builder().latency_threshold(LatencyStatistic::P99, Duration::ZERO))
+                .await,
+            "The p99 threshold must be at least a millisecond"
+        );
+        assert_eq!(
+            get_build_error(builder().expect_status(600)).await,
+            "The status code 600 isn't valid"
+        );
+        assert_eq!(
+            get_build_error(builder().max_error_rate(f64::NAN)).await,
+            "The maximum error rate of NaN% must be from 0 to 100%"
+        );
+        assert_eq!(
+            get_build_error(builder().header("Bad Name", "value")).await,
+            "The header name Bad Name isn't valid"
+        );
+        assert_eq!(
+            get_build_error(LoadTestBuilder::new()).await,
+            "The load test needs a URL, or another source of requests (such as --scenario) in its args"
+        );
+        assert_eq!(
+            get_build_error(builder().args(["--not-an-option"])).await,
+            "The load test's args (--not-an-option) aren't valid, or can't be given with its other options"
+        );
+    }
+
+    #[tokio::test]
+    async fn build_accepts_urls_with_placeholders() {
+        let load_test = LoadTestBuilder::new()
+            .url("http://{{host}}/users/{{seq}}")
+            .build()
+            .await;
+
+        assert!(load_test.is_ok());
+    }
+
+    #[tokio::test]
+    async fn build_gives_the_retries_expected_statuses_and_thresholds_as_options() {
+        let load_test = LoadTestBuilder::new()
+            .url("http://localhost:8080")
+            .retries(2)
+            .expect_status(204)
+            .expect_status(404)
+            .latency_threshold(LatencyStatistic::P99, Duration::from_millis(250))
+            .latency_threshold(LatencyStatistic::Avg, Duration::from_secs(1))
+            .max_error_rate(1.5)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(load_test.args.retries, 2);
+        assert_eq!(load_test.args.expect_status, ["204", "404"]);
+        assert_eq!(
+            load_test.args.latency_thresholds,
+            [
+                (String::from("p99"), Duration::from_millis(250)),
+                (String::from("avg"), Duration::from_secs(1))
+            ]
+        );
+        assert_eq!(load_test.args.max_error_rate, Some(1.5));
+    }
+
+    #[tokio::test]
+    async fn build_sends_bodies_as_they_are() {
+        let load_test = LoadTestBuilder::new()
+            .url("http://localhost:8080")
+            .method(Method::POST)
+            .body("@not-a-file")
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(load_test.args.body.as_deref(), Some(&b"@not-a-file"[..]));
+    }
+}
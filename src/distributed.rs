@@ -0,0 +1,493 @@
+//! Generating load from more than one machine, with workers that run the plans a controller sends them and the
+//! controller merging their results into one run.
+
+use std::{borrow::Cow, net::SocketAddr, time::Duration};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tokio_native_tls::{TlsAcceptor, TlsConnector};
+use tokio_util::either::Either;
+
+use crate::{args::*, report::*, runner::*, serve::*, stats::*};
+
+/// The port that workers listen for plans on unless they're told otherwise.
+pub(crate) const DEFAULT_WORKER_PORT: u16 = 7000;
+
+/// Represents a worker's share of a run, being the options for it to run with as if they were given on its command
+/// line, along with the token that the worker was started with and its place among the run's workers.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct WorkerPlan {
+    pub(crate) token: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) worker: usize,
+    pub(crate) workers: usize,
+}
+
+/// Represents what a worker sends back once it has run a plan: everything needed to report on its share of the run,
+/// or why it couldn't be run.
+pub(crate) type WorkerReport = Result<SavedRun<'static>, String>;
+
+/// Listens for plans from controllers, running each that was sent with the worker's token (one after another, so that
+/// they don't compete with each other) and sending its results back to the controller that sent it, over TLS if the
+/// worker was given a certificate.
+pub(crate) async fn run_worker(address: SocketAddr, token: String, tls: Option<TlsAcceptor>) {
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen for plans on {0}: {1}", address, e);
+            return;
+        }
+    };
+    println!("👷 Waiting for plans on {0}\n", address);
+    if tls.is_none() && !address.ip().is_loopback() {
+        println!(
+            "⚠️  Plans (with the token and any secrets in their options) and their results are sent unencrypted without \
+             a --cert, so only listen on a trusted network\n"
+        );
+    }
+
+    loop {
+        let (stream, controller) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Failed to accept a controller's connection: {0}", e);
+                continue;
+            }
+        };
+
+        let result = match &tls {
+            Some(tls) => match tls.accept(stream).await {
+                Ok(stream) => run_plan(stream, controller, &token).await,
+                Err(e) => Err(std::io::Error::other(e)),
+            },
+            None => run_plan(stream, controller, &token).await,
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to run the plan from {0}: {1}\n", controller, e);
+        }
+    }
+}
+
+/// Reads a plan from a controller, runs it if it was sent with the worker's token and sends the results back.
+pub(crate) async fn run_plan(
+    stream: impl AsyncRead + AsyncWrite,
+    controller: SocketAddr,
+    token: &str,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    // Controllers connect to every worker before sending any plans, so they disconnect without one if any can't be.
+    if line.is_empty() {
+        return Ok(());
+    }
+    let plan: WorkerPlan = serde_json::from_str(&line)?;
+
+    println!("📋 Running a plan from {0}", controller);
+    let report = if !tokens_match(&plan.token, token) {
+        Err(String::from("The plan wasn't sent with the worker's token"))
+    } else if let Some(option) = find_local_file_option(&plan.args) {
+        Err(format!(
+            "{0} can't be given to a run on workers, as it reads or writes files on the machine running it",
+            option
+        ))
+    } else {
+        get_worker_report(plan.args, (plan.worker, plan.workers)).await
+    };
+    match &report {
+        Ok(saved) => println!(
+            "\t{0} requests completed in {1:.1}s, sending the results back\n",
+            saved.results.len(),
+            saved.elapsed.as_secs_f64()
+        ),
+        Err(e) => println!("\t{0}\n", e),
+    }
+
+    let mut body = serde_json::to_vec(&report)?;
+    body.push(b'\n');
+    writer.write_all(&body).await?;
+    writer.shutdown().await
+}
+
+/// Runs a worker's share of a run, giving everything needed to report on it.
+pub(crate) async fn get_worker_report(
+    args: Vec<String>,
+    sequence_share: (usize, usize),
+) -> WorkerReport {
+    let mut config = TestConfig::parse(args.clone())
+        .ok_or_else(|| String::from("Failed to parse the plan's options"))?;
+    if sequence_share.0 >= sequence_share.1 {
+        return Err(String::from("The plan's worker isn't one of its workers"));
+    }
+    config.args.sequence_share = sequence_share;
+    let load_test = LoadTest::new(config).await?;
+    let mut progress = None;
+    let test_results = load_test
+        .run_watched(|state| progress = Some(print_progress(state, load_test.args.clone())))
+        .await;
+    if let Some(progress) = progress {
+        progress.abort();
+    }
+    let test_results = test_results?;
+    let state = &test_results.state;
+
+    let saved = SavedRun {
+        args: Cow::Owned(args),
+        elapsed: test_results.elapsed,
+        http3_handshake: state.http3_handshake,
+        events: Cow::Owned(std::mem::take(&mut *state.events.lock().unwrap())),
+        pacing_overruns: Cow::Owned(std::mem::take(&mut *state.pacing_overruns.lock().unwrap())),
+        dns_lookups: Cow::Owned(std::mem::take(&mut *state.dns_lookups.lock().unwrap())),
+        in_flight_samples: Cow::Owned(std::mem::take(
+            &mut *state.in_flight_samples.lock().unwrap(),
+        )),
+        failure_bodies: Cow::Owned(std::mem::take(&mut *state.failure_bodies.lock().unwrap())),
+        results: Cow::Owned(test_results.results),
+    };
+    Ok(saved)
+}
+
+/// Gets what a worker accepts connections over TLS with, from its certificate (and any intermediates) and PKCS #8 key,
+/// which can be in the same file.
+pub(crate) fn get_worker_tls_acceptor(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<TlsAcceptor, String> {
+    let (certs, key) = read_identity_pems(cert_path, key_path)?;
+    let identity = native_tls::Identity::from_pkcs8(certs.as_bytes(), key.as_bytes())
+        .map_err(|e| e.to_string())?;
+    let acceptor = native_tls::TlsAcceptor::new(identity).map_err(|e| e.to_string())?;
+    Ok(acceptor.into())
+}
+
+/// Gets what a controller connects to workers over TLS with, trusting the certificate authorities in a PEM bundle as
+/// well as the system's.
+pub(crate) fn get_worker_tls_connector(ca_cert_path: &str) -> Result<TlsConnector, String> {
+    let pem = std::fs::read_to_string(ca_cert_path).map_err(|e| e.to_string())?;
+    let mut tls = native_tls::TlsConnector::builder();
+    for cert in get_pem_blocks(&pem, "CERTIFICATE").split_inclusive("-----END CERTIFICATE-----") {
+        if !cert.trim().is_empty() {
+            tls.add_root_certificate(
+                native_tls::Certificate::from_pem(cert.as_bytes()).map_err(|e| e.to_string())?,
+            );
+        }
+    }
+    Ok(tls.build().map_err(|e| e.to_string())?.into())
+}
+
+/// Gets one worker's share of a total, with whatever doesn't divide equally going to the first workers.
+pub(crate) fn get_worker_share(total: usize, worker: usize, workers: usize) -> usize {
+    total / workers + usize::from(worker < total % workers)
+}
+
+/// Gets the options for a worker to run its share of the run with, which are the same as the controller's except for
+/// the count, rate, virtual users and concurrency being shared out between the workers, and without the options that
+/// the controller handles itself once the workers' results are merged (saving, exporting and reporting on the run and
+/// comparing it against a baseline) or that only it needs (the workers and their token).
+pub(crate) fn get_worker_args(raw_args: &[String], worker: usize, workers: usize) -> Vec<String> {
+    let mut worker_args = vec![raw_args[0].clone()];
+
+    let mut iterator = if raw_args.get(1).map(String::as_str) == Some("run") {
+        2
+    } else {
+        1
+    };
+    while iterator < raw_args.len() {
+        let (arg, value) = (&raw_args[iterator], raw_args.get(iterator + 1));
+        match (arg.as_str(), value) {
+            (
+                "--workers"
+                | "--token"
+                | "--worker-cacert"
+                | "--save"
+                | "--export"
+                | "--report"
+                | "--latency-distribution"
+                | "--export-hgrm"
+                | "--baseline",
+                Some(_),
+            ) => {}
+            (
+                "-c" | "--count" | "--vus" | "-p" | "--concurrency" | "--warmup-requests",
+                Some(value),
+            ) => {
+                let total = value.parse().unwrap_or_default();
+                worker_args.push(arg.clone());
+                worker_args.push(get_worker_share(total, worker, workers).to_string());
+            }
+            ("-r" | "--rate", Some(value)) => {
+                let rate = parse_rate(value).unwrap_or_default();
+                worker_args.push(arg.clone());
+                worker_args.push((rate / workers as f64).to_string());
+            }
+            _ => {
+                worker_args.push(arg.clone());
+                iterator += 1;
+                continue;
+            }
+        }
+        iterator += 2;
+    }
+
+    worker_args
+}
+
+/// Sends each worker its share of the run and waits for them all to finish, giving each worker's report.
+pub(crate) async fn run_on_workers(
+    raw_args: &[String],
+    args: &ParsedArgs,
+) -> Result<Vec<SavedRun<'static>>, String> {
+    // Workers can't read or write their own files for a run, so a run that would is stopped before it reaches them.
+    if let Some(option) = find_local_file_option(&get_worker_args(raw_args, 0, 1)) {
+        return Err(format!(
+            "{0} can't be given to a run on workers, as it reads or writes files on the machines running it",
+            option
+        ));
+    }
+
+    // Every worker is connected to before any are sent their plan, so that none start if any can't.
+    let tls = match &args.worker_ca_cert_path {
+        Some(path) => Some(get_worker_tls_connector(path)?),
+        None => None,
+    };
+    let streams = futures::future::try_join_all(args.workers.iter().map(|worker| {
+        let tls = &tls;
+        async move {
+            let stream = TcpStream::connect(worker)
+                .await
+                .map_err(|e| format!("Failed to connect to the worker at {0}: {1}", worker, e))?;
+            let Some(tls) = tls else {
+                return Ok(Either::Right(stream));
+            };
+
+            let (host, _) = worker.rsplit_once(':').unwrap_or((worker, ""));
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            tls.connect(host, stream)
+                .await
+                .map(Either::Left)
+                .map_err(|e| {
+                    format!(
+                        "Failed to connect to the worker at {0} over TLS: {1}",
+                        worker, e
+                    )
+                })
+        }
+    }))
+    .await?;
+
+    println!(
+        "👷 Sent the plan to {0} workers, waiting for them to finish",
+        streams.len()
+    );
+
+    let workers = args.workers.len();
+    futures::future::try_join_all(streams.into_iter().zip(&args.workers).enumerate().map(
+        |(index, (stream, worker))| async move {
+            let plan = WorkerPlan {
+                token: args.worker_token.clone().unwrap_or_default(),
+                args: get_worker_args(raw_args, index, workers),
+                worker: index,
+                workers,
+            };
+            let saved = send_plan(stream, &plan)
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Failed to get the results from the worker at {0}: {1}",
+                        worker, e
+                    )
+                })?
+                .map_err(|e| format!("The worker at {0} couldn't run its plan: {1}", worker, e))?;
+
+            println!(
+                "\t{0: <24} | {1} requests completed in {2:.1}s",
+                worker,
+                saved.results.len(),
+                saved.elapsed.as_secs_f64()
+            );
+            Ok(saved)
+        },
+    ))
+    .await
+}
+
+/// Sends a plan to a worker and reads back its report once it has been run.
+pub(crate) async fn send_plan(
+    stream: impl AsyncRead + AsyncWrite,
+    plan: &WorkerPlan,
+) -> std::io::Result<WorkerReport> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut body = serde_json::to_vec(plan)?;
+    body.push(b'\n');
+    writer.write_all(&body).await?;
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Merges the runs of each worker into one, as if all of their requests had been sent from the same machine. The
+/// workers start at roughly the same time, so their timelines are lined up from their starts.
+pub(crate) fn merge_worker_runs(
+    raw_args: &[String],
+    runs: Vec<SavedRun<'static>>,
+) -> SavedRun<'static> {
+    let handshakes = runs.iter().filter_map(|r| r.http3_handshake).collect_vec();
+    let samples = runs
+        .iter()
+        .map(|r| r.in_flight_samples.len())
+        .max()
+        .unwrap_or_default();
+    let in_flight_samples = (0..samples)
+        .map(|i| {
+            let samples = runs
+                .iter()
+                .filter_map(|r| r.in_flight_samples.get(i))
+                .collect_vec();
+            InFlightSample {
+                at: samples.iter().map(|s| s.at).max().unwrap_or_default(),
+                in_flight: samples.iter().map(|s| s.in_flight).sum(),
+                peak: samples.iter().map(|s| s.peak).sum(),
+            }
+        })
+        .collect();
+
+    // Bodies sampled by more than one worker are counted together, and only the first few distinct bodies are kept, as
+    // they are on one machine.
+    let mut failure_bodies = Vec::<FailureBody>::new();
+    for sample in runs.iter().flat_map(|r| r.failure_bodies.iter()) {
+        if let Some(merged) = failure_bodies
+            .iter_mut()
+            .find(|b| b.status == sample.status && b.body == sample.body)
+        {
+            merged.count += sample.count;
+        } else if failure_bodies.len() < MAX_FAILURE_BODY_SAMPLES {
+            failure_bodies.push(sample.clone());
+        }
+    }
+
+    SavedRun {
+        args: Cow::Owned(raw_args.to_vec()),
+        elapsed: runs.iter().map(|r| r.elapsed).max().unwrap_or_default(),
+        http3_handshake: (!handshakes.is_empty())
+            .then(|| handshakes.iter().sum::<Duration>() / handshakes.len() as u32),
+        events: Cow::Owned(
+            runs.iter()
+                .flat_map(|r| r.events.iter().cloned())
+                .sorted_by_key(|e| e.at)
+                .collect(),
+        ),
+        pacing_overruns: Cow::Owned(
+            runs.iter()
+                .flat_map(|r| r.pacing_overruns.iter().copied())
+                .collect(),
+        ),
+        dns_lookups: Cow::Owned(
+            runs.iter()
+                .flat_map(|r| r.dns_lookups.iter().copied())
+                .collect(),
+        ),
+        in_flight_samples: Cow::Owned(in_flight_samples),
+        failure_bodies: Cow::Owned(failure_bodies),
+        results: Cow::Owned(
+            runs.into_iter()
+                .flat_map(|r| r.results.into_owned())
+                .sorted_by_key(|r| r.completed_at)
+                .collect(),
+        ),
+    }
+}
+
+/// Runs a load test on the workers, giving the merged results of them all and the state of the run as it was at the
+/// end.
+pub(crate) async fn get_worker_results(
+    raw_args: &[String],
+    args: &ParsedArgs,
+) -> Result<(Vec<ResponseStatistics>, RunState), String> {
+    let runs = run_on_workers(raw_args, args).await?;
+    Ok(get_saved_run_state(args, merge_worker_runs(raw_args, runs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends a plan to a worker with the token `s3cret` over an in-memory connection, giving the worker's report.
+    async fn send_test_plan(token: &str, args: &[&str]) -> WorkerReport {
+        let (controller, worker) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            run_plan(worker, SocketAddr::from(([127, 0, 0, 1], 0)), "s3cret").await
+        });
+        let plan = WorkerPlan {
+            token: token.to_owned(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            worker: 0,
+            workers: 1,
+        };
+        send_plan(controller, &plan).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn run_plan_rejects_plans_without_the_token_or_that_touch_local_files() {
+        let report = send_test_plan("guess", &["smashit", "-u", "http://localhost"]).await;
+        assert_eq!(
+            report.err().as_deref(),
+            Some("The plan wasn't sent with the worker's token")
+        );
+
+        let report = send_test_plan(
+            "s3cret",
+            &["smashit", "-u", "http://localhost", "-b", "@/etc/passwd"],
+        )
+        .await;
+        assert!(report.is_err_and(|e| e.starts_with("-b can't be given")));
+    }
+
+    #[test]
+    fn get_worker_share_gives_the_remainder_to_the_first_workers() {
+        let shares = (0..3).map(|w| get_worker_share(10, w, 3)).collect_vec();
+
+        assert_eq!(shares, [4, 3, 3]);
+    }
+
+    #[test]
+    fn get_worker_args_shares_out_the_load_and_leaves_out_the_controllers_options() {
+        let raw_args = [
+            "smashit",
+            "run",
+            "-u",
+            "http://localhost",
+            "-c",
+            "10",
+            "-r",
+            "5",
+            "--workers",
+            "a:7000,b:7000",
+            "--token",
+            "s3cret",
+            "--save",
+            "run.json",
+            "--http2",
+        ]
+        .map(String::from);
+
+        assert_eq!(
+            get_worker_args(&raw_args, 1, 2),
+            [
+                "smashit",
+                "-u",
+                "http://localhost",
+                "-c",
+                "5",
+                "-r",
+                "2.5",
+                "--http2"
+            ]
+        );
+    }
+}
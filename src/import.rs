@@ -0,0 +1,1322 @@
+//! Importing the requests to load test from the forms they're often already saved in, such as curl commands, HAR files,
+//! Postman collections and OpenAPI specs, so that they don't have to be translated into smashit's options by hand.
+
+use std::collections::{HashMap, VecDeque};
+
+use bytes::Bytes;
+use itertools::Itertools;
+use reqwest::Method;
+use serde::Deserialize;
+
+use crate::args::*;
+
+/// Represents the request that a curl command sends, along with the options it sends it with that smashit also has.
+pub(crate) struct CurlCommand {
+    pub(crate) url: String,
+    pub(crate) method: Method,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Option<Bytes>,
+    pub(crate) basic_auth: Option<(String, String)>,
+    pub(crate) cookies: Vec<(String, String)>,
+    pub(crate) insecure: bool,
+    pub(crate) http_version: Option<HttpVersionPreference>,
+    /// Whether it asks for a compressed response (with --compressed), as smashit's --compressed does.
+    pub(crate) compressed: bool,
+}
+
+/// The curl options that don't take a value and don't change the request that's sent, such as those controlling its
+/// output, which are ignored.
+pub(crate) const IGNORED_CURL_FLAGS: [&str; 17] = [
+    "-s",
+    "--silent",
+    "-S",
+    "--show-error",
+    "-v",
+    "--verbose",
+    "-i",
+    "--include",
+    "-L",
+    "--location",
+    "-f",
+    "--fail",
+    "-N",
+    "--no-buffer",
+    "-g",
+    "--globoff",
+    "--no-progress-meter",
+];
+
+/// The curl options that don't take a value which can be combined with other short options, e.g. -sSLk, whether or not
+/// they change the request that's sent.
+pub(crate) const COMBINABLE_CURL_FLAGS: &str = "sSviLfNgkGI";
+
+/// The curl options that take a value but don't change the request that's sent, which are ignored along with it.
+pub(crate) const IGNORED_CURL_OPTIONS: [&str; 8] = [
+    "-o",
+    "--output",
+    "-w",
+    "--write-out",
+    "-m",
+    "--max-time",
+    "--connect-timeout",
+    "--retry",
+];
+
+/// Parses a curl command (e.g. one copied from a browser's developer tools) into the request that it sends. Its
+/// arguments are split as a POSIX shell would, with quotes, escapes and line continuations.
+pub(crate) fn parse_curl_command(command: &str) -> Result<CurlCommand, String> {
+    let mut words = VecDeque::from(split_shell_words(command)?);
+    if words.front().map(String::as_str) == Some("curl") {
+        words.pop_front();
+    }
+
+    let mut curl = CurlCommand {
+        url: String::new(),
+        method: Method::GET,
+        headers: vec![],
+        body: None,
+        basic_auth: None,
+        cookies: vec![],
+        insecure: false,
+        http_version: None,
+        compressed: false,
+    };
+    let mut method: Option<Method> = None;
+    let mut data: Vec<Vec<u8>> = vec![];
+    let mut data_as_query = false;
+    let mut head = false;
+
+    while let Some(word) = words.pop_front() {
+        // Flags are often combined, e.g. -sSk, so they're taken one at a time.
+        if let Some(flags) = split_combined_flags(&word) {
+            for flag in flags.into_iter().rev() {
+                words.push_front(flag);
+            }
+            continue;
+        }
+
+        // Short options can have their values joined onto them, e.g. -XPOST.
+        let (option, joined_value) = match word.get(..2) {
+            Some(short) if short.starts_with('-') && !word.starts_with("--") && word.len() > 2 => {
+                (short, Some(word[2..].to_owned()))
+            }
+            _ => (word.as_str(), None),
+        };
+        let mut value = || {
+            joined_value
+                .clone()
+                .or_else(|| words.pop_front())
+                .ok_or_else(|| format!("curl's {0} option needs a value", option))
+        };
+
+        match option {
+            "-X" | "--request" => {
+                method = Some(
+                    Method::from_bytes(value()?.to_ascii_uppercase().as_bytes())
+                        .map_err(|_| String::from("the method isn't valid"))?,
+                )
+            }
+            "-H" | "--header" => {
+                let header = value()?;
+                let Some((name, value)) = header.split_once(':') else {
+                    return Err(format!(
+                        "the header {0:?} isn't in the format of Name: value",
+                        header
+                    ));
+                };
+                curl.headers
+                    .push((name.trim().to_owned(), value.trim().to_owned()));
+            }
+            "-d" | "--data" | "--data-ascii" | "--data-binary" => {
+                let value = value()?;
+                data.push(match value.strip_prefix('@') {
+                    Some(path) => {
+                        let mut contents = std::fs::read(path).map_err(|e| {
+                            format!("failed to read the data from {0}: {1}", path, e)
+                        })?;
+                        // As curl does, only --data-binary sends a file's line breaks.
+                        if option != "--data-binary" {
+                            contents.retain(|b| *b != b'\r' && *b != b'\n');
+                        }
+                        contents
+                    }
+                    None => value.as_bytes().to_vec(),
+                });
+            }
+            "--data-raw" => data.push(value()?.as_bytes().to_vec()),
+            "--data-urlencode" => {
+                let value = value()?;
+                data.push(
+                    match value.split_once('=') {
+                        Some((name, content)) => format!("{0}={1}", name, percent_encode(content)),
+                        None => percent_encode(&value),
+                    }
+                    .into_bytes(),
+                );
+            }
+            "--json" => {
+                data.push(value()?.as_bytes().to_vec());
+                curl.headers.push((
+                    String::from("Content-Type"),
+                    String::from("application/json"),
+                ));
+                curl.headers
+                    .push((String::from("Accept"), String::from("application/json")));
+            }
+            "-G" | "--get" => data_as_query = true,
+            "-I" | "--head" => head = true,
+            "-u" | "--user" => {
+                let user = value()?;
+                let (username, password) = user.split_once(':').unwrap_or((&user, ""));
+                curl.basic_auth = Some((username.to_owned(), password.to_owned()));
+            }
+            "-A" | "--user-agent" => curl
+                .headers
+                .push((String::from("User-Agent"), value()?.to_owned())),
+            "-e" | "--referer" => curl
+                .headers
+                .push((String::from("Referer"), value()?.to_owned())),
+            "-b" | "--cookie" => {
+                let cookies = value()?;
+                if !cookies.contains('=') {
+                    return Err(String::from(
+                        "cookies can't be read from a file, only given as NAME=VALUE",
+                    ));
+                }
+                curl.cookies.extend(cookies.split(';').filter_map(|c| {
+                    let (name, value) = c.split_once('=')?;
+                    Some((name.trim().to_owned(), value.trim().to_owned()))
+                }));
+            }
+            "-k" | "--insecure" => curl.insecure = true,
+            "--compressed" => curl.compressed = true,
+            "--http1.1" => curl.http_version = Some(HttpVersionPreference::Http1),
+            "--http2" | "--http2-prior-knowledge" => {
+                curl.http_version = Some(HttpVersionPreference::Http2)
+            }
+            "--url" => curl.url = value()?.to_owned(),
+            _ if IGNORED_CURL_OPTIONS.contains(&option) => {
+                value()?;
+            }
+            _ if IGNORED_CURL_FLAGS.contains(&word.as_str()) => {}
+            _ if word.starts_with('-') && word.len() > 1 => {
+                return Err(format!("curl's {0} option isn't supported", word))
+            }
+            _ => curl.url = word.to_owned(),
+        }
+    }
+
+    if curl.url.is_empty() {
+        return Err(String::from("it doesn't have a URL"));
+    }
+    // curl assumes plain HTTP for URLs without a scheme.
+    if !curl.url.contains("://") {
+        curl.url = format!("http://{0}", curl.url);
+    }
+
+    // Data is sent as a form, unless it's sent as the URL's query instead.
+    if !data.is_empty() {
+        let data = data.join(&b'&');
+        if data_as_query {
+            let separator = if curl.url.contains('?') { '&' } else { '?' };
+            curl.url = format!(
+                "{0}{1}{2}",
+                curl.url,
+                separator,
+                String::from_utf8_lossy(&data)
+            );
+        } else {
+            if !curl
+                .headers
+                .iter()
+                .any(|(h, _)| h.eq_ignore_ascii_case("content-type"))
+            {
+                curl.headers.push((
+                    String::from("Content-Type"),
+                    String::from("application/x-www-form-urlencoded"),
+                ));
+            }
+            curl.body = Some(Bytes::from(data));
+        }
+    }
+
+    curl.method = match (method, head, &curl.body) {
+        (Some(method), _, _) => method,
+        (None, true, _) => Method::HEAD,
+        (None, false, Some(_)) => Method::POST,
+        (None, false, None) => Method::GET,
+    };
+
+    Ok(curl)
+}
+
+/// Splits short flags that are combined into one argument, e.g. -sSk, into one argument each, up to the first that
+/// isn't a flag, which takes the rest of the argument as its value, e.g. -sXPOST. Nothing is given for arguments that
+/// don't start with a flag that can be combined.
+pub(crate) fn split_combined_flags(word: &str) -> Option<Vec<String>> {
+    let flags = word
+        .strip_prefix('-')
+        .filter(|f| f.len() > 1 && f.starts_with(|c| COMBINABLE_CURL_FLAGS.contains(c)))?;
+
+    let mut split = vec![];
+    for (position, flag) in flags.char_indices() {
+        if !COMBINABLE_CURL_FLAGS.contains(flag) {
+            split.push(format!("-{0}", &flags[position..]));
+            break;
+        }
+        split.push(format!("-{0}", flag));
+    }
+    Some(split)
+}
+
+/// Represents a HAR file as it's written, e.g. by a browser's developer tools, with only what's needed to send its
+/// requests again.
+#[derive(Deserialize)]
+pub(crate) struct HarFile {
+    pub(crate) log: HarLog,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HarLog {
+    pub(crate) entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HarEntry {
+    pub(crate) request: HarRequest,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HarRequest {
+    pub(crate) method: String,
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) headers: Vec<HarHeader>,
+    pub(crate) post_data: Option<HarPostData>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HarHeader {
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HarPostData {
+    pub(crate) text: Option<String>,
+}
+
+/// The headers of a HAR file's requests that aren't sent again, as they describe the connection that the browser sent
+/// them over rather than the requests themselves, and are set by the client for each request anyway.
+pub(crate) const EXCLUDED_HAR_HEADERS: [&str; 5] = [
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+];
+
+/// Parses the entries of a HAR file into a scenario whose steps are its requests, in the order they were sent. Entries
+/// for anything other than HTTP or HTTPS URLs, such as data URLs, are skipped.
+pub(crate) fn parse_har_file(path: &str) -> Option<Scenario> {
+    let file: HarFile = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+    {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to read the HAR file at {0}: {1}", path, e);
+            return None;
+        }
+    };
+
+    let steps = file
+        .log
+        .entries
+        .into_iter()
+        .filter(|e| e.request.url.starts_with("http://") || e.request.url.starts_with("https://"))
+        .map(|entry| {
+            let request = entry.request;
+            let method = Method::from_bytes(request.method.to_ascii_uppercase().as_bytes()).ok()?;
+
+            // HTTP/2's pseudo-headers (e.g. :authority) are part of the request line rather than headers of their own.
+            let headers = request
+                .headers
+                .into_iter()
+                .filter(|h| {
+                    !h.name.starts_with(':')
+                        && !EXCLUDED_HAR_HEADERS.contains(&h.name.to_ascii_lowercase().as_str())
+                })
+                .map(|h| (h.name, h.value))
+                .collect();
+
+            Some(ScenarioStep {
+                name: format!("{0} {1}", method, request.url),
+                method,
+                url: request.url,
+                headers,
+                body: request.post_data.and_then(|p| p.text),
+                extractions: vec![],
+                think_time: None,
+                retry_non_idempotent: false,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    if steps.is_empty() {
+        eprintln!("The HAR file at {0} doesn't have any HTTP requests", path);
+        return None;
+    }
+
+    Some(Scenario {
+        path: path.to_string(),
+        steps,
+        rate: None,
+    })
+}
+
+/// Represents a Postman collection as it's written in the v2.1 format, with only what's needed to send its requests.
+#[derive(Deserialize)]
+pub(crate) struct PostmanCollection {
+    #[serde(default)]
+    pub(crate) item: Vec<PostmanItem>,
+    #[serde(default)]
+    pub(crate) variable: Vec<PostmanVariable>,
+    pub(crate) auth: Option<PostmanAuth>,
+}
+
+/// Represents an item in a Postman collection, which is either a request or a folder of more items.
+#[derive(Deserialize)]
+pub(crate) struct PostmanItem {
+    #[serde(default)]
+    pub(crate) name: String,
+    pub(crate) request: Option<PostmanRequest>,
+    #[serde(default)]
+    pub(crate) item: Vec<PostmanItem>,
+    pub(crate) auth: Option<PostmanAuth>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PostmanRequest {
+    pub(crate) method: Option<String>,
+    pub(crate) url: Option<PostmanUrl>,
+    #[serde(default)]
+    pub(crate) header: Vec<PostmanVariable>,
+    pub(crate) body: Option<PostmanBody>,
+    pub(crate) auth: Option<PostmanAuth>,
+}
+
+/// Represents a request's URL, which Postman writes either as text or broken down into its parts along with the text.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum PostmanUrl {
+    Raw(String),
+    Parts { raw: String },
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PostmanBody {
+    pub(crate) mode: String,
+    pub(crate) raw: Option<String>,
+    #[serde(default)]
+    pub(crate) urlencoded: Vec<PostmanVariable>,
+    pub(crate) graphql: Option<serde_json::Value>,
+    pub(crate) options: Option<serde_json::Value>,
+}
+
+/// Represents how a Postman request (or every request in its folder or collection) is authorized, with its options
+/// (e.g. a bearer token's `token`) given as variables.
+#[derive(Deserialize)]
+pub(crate) struct PostmanAuth {
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+    #[serde(default)]
+    pub(crate) bearer: Vec<PostmanVariable>,
+    #[serde(default)]
+    pub(crate) basic: Vec<PostmanVariable>,
+    #[serde(default)]
+    pub(crate) apikey: Vec<PostmanVariable>,
+}
+
+/// Represents a key and its value in a Postman collection or environment, such as one of its variables or a request's
+/// headers, which can be disabled.
+#[derive(Deserialize)]
+pub(crate) struct PostmanVariable {
+    pub(crate) key: String,
+    #[serde(default)]
+    pub(crate) value: serde_json::Value,
+    #[serde(default)]
+    pub(crate) disabled: bool,
+    pub(crate) enabled: Option<bool>,
+}
+
+/// Represents a Postman environment file, being the values of the variables that the collection's requests use.
+#[derive(Deserialize)]
+pub(crate) struct PostmanEnvironment {
+    #[serde(default)]
+    pub(crate) values: Vec<PostmanVariable>,
+}
+
+/// Postman's dynamic variables (e.g. `{{$guid}}`) and the placeholders they're replaced with, which smashit evaluates
+/// for each request.
+pub(crate) const POSTMAN_DYNAMIC_VARIABLES: [(&str, &str); 12] = [
+    ("$guid", "{{uuid}}"),
+    ("$randomUUID", "{{uuid}}"),
+    ("$timestamp", "{{timestamp}}"),
+    ("$randomInt", "{{rand_int 0 1000}}"),
+    ("$randomFirstName", "{{fake.first_name}}"),
+    ("$randomLastName", "{{fake.last_name}}"),
+    ("$randomFullName", "{{fake.name}}"),
+    ("$randomUserName", "{{fake.username}}"),
+    ("$randomEmail", "{{fake.email}}"),
+    ("$randomPhoneNumber", "{{fake.phone}}"),
+    ("$randomIP", "{{fake.ipv4}}"),
+    ("$randomWord", "{{fake.word}}"),
+];
+
+impl PostmanVariable {
+    /// Whether the variable is used, as either collections or environments can turn them off.
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.disabled && self.enabled.unwrap_or(true)
+    }
+
+    pub(crate) fn get_value(&self) -> String {
+        match &self.value {
+            serde_json::Value::String(value) => value.clone(),
+            serde_json::Value::Null => String::new(),
+            value => value.to_string(),
+        }
+    }
+}
+
+/// Parses a Postman collection into a scenario whose steps are its requests, in the order they appear in it (including
+/// in its folders), with the variables they use replaced by their values in the environment or otherwise the
+/// collection. Those without values, such as the columns of a data file, are left as placeholders.
+pub(crate) fn parse_postman_collection(path: &str, env_path: Option<&str>) -> Option<Scenario> {
+    let read = |path: &str| std::fs::read_to_string(path).map_err(|e| e.to_string());
+    let collection = read(path)
+        .and_then(|s| serde_json::from_str::<PostmanCollection>(&s).map_err(|e| e.to_string()));
+    let environment = env_path.map(|env_path| {
+        read(env_path)
+            .and_then(|s| serde_json::from_str::<PostmanEnvironment>(&s).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                format!(
+                    "Failed to read the Postman environment at {0}: {1}",
+                    env_path, e
+                )
+            })
+    });
+
+    let (collection, environment) = match (collection, environment.transpose()) {
+        (Ok(collection), Ok(environment)) => (collection, environment),
+        (Err(e), _) => {
+            eprintln!("Failed to read the Postman collection at {0}: {1}", path, e);
+            return None;
+        }
+        (_, Err(e)) => {
+            eprintln!("{0}", e);
+            return None;
+        }
+    };
+
+    let variables: HashMap<String, String> = collection
+        .variable
+        .iter()
+        .chain(environment.iter().flat_map(|e| &e.values))
+        .filter(|v| v.is_enabled())
+        .map(|v| (v.key.clone(), v.get_value()))
+        .collect();
+
+    let mut steps = vec![];
+    let added = add_postman_steps(
+        &mut steps,
+        &collection.item,
+        "",
+        collection.auth.as_ref(),
+        &variables,
+    );
+    if let Err(e) = added {
+        eprintln!(
+            "Failed to import the Postman collection at {0}: {1}",
+            path, e
+        );
+        return None;
+    }
+
+    if steps.is_empty() {
+        eprintln!(
+            "The Postman collection at {0} doesn't have any requests",
+            path
+        );
+        return None;
+    }
+
+    Some(Scenario {
+        path: path.to_string(),
+        steps,
+        rate: None,
+    })
+}
+
+/// Adds the steps for the requests among some of a Postman collection's items, and those in its folders, with the
+/// authorization of the folder (or collection) that they're in unless they have their own.
+pub(crate) fn add_postman_steps(
+    steps: &mut Vec<ScenarioStep>,
+    items: &[PostmanItem],
+    folder: &str,
+    folder_auth: Option<&PostmanAuth>,
+    variables: &HashMap<String, String>,
+) -> Result<(), String> {
+    for item in items {
+        let name = if folder.is_empty() {
+            item.name.clone()
+        } else {
+            format!("{0} / {1}", folder, item.name)
+        };
+        let auth = item.auth.as_ref().or(folder_auth);
+
+        let Some(request) = &item.request else {
+            add_postman_steps(steps, &item.item, &name, auth, variables)?;
+            continue;
+        };
+        let render = |text: &str| render_postman_variables(text, variables);
+
+        let url = match &request.url {
+            Some(PostmanUrl::Raw(raw) | PostmanUrl::Parts { raw }) => render(raw),
+            None => return Err(format!("{0} doesn't have a URL", name)),
+        };
+        let method = request.method.as_deref().unwrap_or("GET");
+        let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| format!("{0}'s method isn't valid", name))?;
+
+        let mut headers = request
+            .header
+            .iter()
+            .filter(|h| h.is_enabled())
+            .map(|h| (render(&h.key), render(&h.get_value())))
+            .collect_vec();
+        let has_header = |headers: &[(String, String)], name: &str| {
+            headers.iter().any(|(h, _)| h.eq_ignore_ascii_case(name))
+        };
+
+        if let Some(auth) = request.auth.as_ref().or(auth) {
+            let option = |options: &[PostmanVariable], key: &str| {
+                options
+                    .iter()
+                    .find(|o| o.key == key)
+                    .map(|o| render(&o.get_value()))
+                    .unwrap_or_default()
+            };
+            match auth.kind.as_str() {
+                "noauth" => {}
+                "bearer" => headers.push((
+                    String::from("Authorization"),
+                    format!("Bearer {0}", option(&auth.bearer, "token")),
+                )),
+                "basic" => headers.push((
+                    String::from("Authorization"),
+                    format!(
+                        "Basic {0}",
+                        base64_encode(
+                            format!(
+                                "{0}:{1}",
+                                option(&auth.basic, "username"),
+                                option(&auth.basic, "password")
+                            )
+                            .as_bytes()
+                        )
+                    ),
+                )),
+                "apikey" if option(&auth.apikey, "in") != "query" => {
+                    headers.push((option(&auth.apikey, "key"), option(&auth.apikey, "value")))
+                }
+                kind => {
+                    return Err(format!(
+                        "{0} uses {1} auth, which isn't supported",
+                        name, kind
+                    ))
+                }
+            }
+        }
+
+        let body = match &request.body {
+            None => None,
+            Some(body) => match body.mode.as_str() {
+                "raw" => {
+                    let language = body
+                        .options
+                        .as_ref()
+                        .and_then(|o| o.pointer("/raw/language")?.as_str());
+                    if language == Some("json") && !has_header(&headers, "content-type") {
+                        headers.push((
+                            String::from("Content-Type"),
+                            String::from("application/json"),
+                        ));
+                    }
+                    body.raw.as_deref().map(render)
+                }
+                "urlencoded" => {
+                    if !has_header(&headers, "content-type") {
+                        headers.push((
+                            String::from("Content-Type"),
+                            String::from("application/x-www-form-urlencoded"),
+                        ));
+                    }
+                    Some(
+                        body.urlencoded
+                            .iter()
+                            .filter(|p| p.is_enabled())
+                            .map(|p| {
+                                format!(
+                                    "{0}={1}",
+                                    percent_encode(&render(&p.key)),
+                                    percent_encode(&render(&p.get_value()))
+                                )
+                            })
+                            .join("&"),
+                    )
+                }
+                "graphql" => {
+                    if !has_header(&headers, "content-type") {
+                        headers.push((
+                            String::from("Content-Type"),
+                            String::from("application/json"),
+                        ));
+                    }
+                    body.graphql.as_ref().map(|g| render(&g.to_string()))
+                }
+                mode => {
+                    return Err(format!(
+                        "{0} has a {1} body, which isn't supported",
+                        name, mode
+                    ))
+                }
+            },
+        };
+
+        steps.push(ScenarioStep {
+            name,
+            method,
+            url,
+            headers,
+            body,
+            extractions: vec![],
+            think_time: None,
+            retry_non_idempotent: false,
+        });
+    }
+
+    Ok(())
+}
+
+/// Replaces the Postman variables (e.g. `{{baseUrl}}`) in some text with their values, and its dynamic variables with
+/// smashit's placeholders. Any others are left as they are, to be evaluated as placeholders for each request.
+pub(crate) fn render_postman_variables(text: &str, variables: &HashMap<String, String>) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|e| start + e + 2) else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..end - 2].trim();
+        match (
+            variables.get(name),
+            POSTMAN_DYNAMIC_VARIABLES.iter().find(|(v, _)| *v == name),
+        ) {
+            (Some(value), _) => rendered.push_str(value),
+            (None, Some((_, placeholder))) => rendered.push_str(placeholder),
+            (None, None) => rendered.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Encodes bytes as standard base64, with padding, as basic auth's credentials are.
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    bytes
+        .chunks(3)
+        .flat_map(|chunk| {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - i * 8));
+            // Each byte short of a full chunk leaves one of its characters as padding.
+            (0..4).map(move |i| {
+                if i <= chunk.len() {
+                    ALPHABET[(n >> (18 - i * 6)) as usize & 63] as char
+                } else {
+                    '='
+                }
+            })
+        })
+        .collect()
+}
+
+/// The methods that an OpenAPI path item can have operations for, in the order they're load tested in.
+pub(crate) const OPENAPI_METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// The most references in a row that are followed to find what they refer to, in case they refer back to each other.
+pub(crate) const MAX_REF_CHAIN: usize = 8;
+
+/// Parses an OpenAPI spec (3.x, as YAML or JSON) into a request for each of its operations with any of the tags, or
+/// every operation if there are none, giving the base URL that they're sent to. That's the one given, or otherwise
+/// the spec's first server.
+pub(crate) fn parse_openapi_spec(
+    path: &str,
+    base_url: &str,
+    tags: Vec<String>,
+) -> Option<(String, OpenApi)> {
+    // JSON is also YAML, so specs in either can be read as YAML.
+    let spec: serde_json::Value = match std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_yaml::from_str(&s).map_err(|e| e.to_string()))
+    {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("Failed to read the OpenAPI spec at {0}: {1}", path, e);
+            return None;
+        }
+    };
+
+    let base_url = match base_url {
+        "" => get_openapi_server_url(&spec),
+        base_url => Some(base_url.to_owned()),
+    };
+    let Some(base_url) = base_url.filter(|u| u.contains("://")) else {
+        eprintln!(
+            "The OpenAPI spec at {0} doesn't have a server with an absolute URL, so one needs to be given with -u",
+            path
+        );
+        return None;
+    };
+
+    let mut operations = vec![];
+    for (route, item) in spec["paths"].as_object().into_iter().flatten() {
+        let item = resolve_ref(&spec, item);
+        for method in OPENAPI_METHODS {
+            let Some(operation) = item.get(method).map(|o| resolve_ref(&spec, o)) else {
+                continue;
+            };
+            let is_tagged = operation["tags"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|t| tags.iter().any(|tag| t.as_str() == Some(tag)));
+            if !tags.is_empty() && !is_tagged {
+                continue;
+            }
+
+            operations.push(get_openapi_operation(
+                &spec, &base_url, route, method, item, operation,
+            ));
+        }
+    }
+
+    if operations.is_empty() {
+        eprintln!(
+            "The OpenAPI spec at {0} doesn't have any operations{1}",
+            path,
+            if tags.is_empty() {
+                String::new()
+            } else {
+                format!(" tagged {0}", tags.join(" or "))
+            }
+        );
+        return None;
+    }
+
+    Some((
+        base_url,
+        OpenApi {
+            path: path.to_string(),
+            tags,
+            operations,
+        },
+    ))
+}
+
+/// Gets the URL of the first server in an OpenAPI spec, with its variables replaced by their defaults.
+pub(crate) fn get_openapi_server_url(spec: &serde_json::Value) -> Option<String> {
+    let server = &spec["servers"][0];
+    let mut url = server["url"].as_str()?.to_owned();
+
+    for (name, variable) in server["variables"].as_object().into_iter().flatten() {
+        if let Some(default) = variable["default"].as_str() {
+            url = url.replace(&format!("{{{0}}}", name), default);
+        }
+    }
+
+    Some(url)
+}
+
+/// Builds the request for an OpenAPI operation, with the examples of its path, required query and required header
+/// parameters, and of its JSON body, which are made up from their schemas where the spec doesn't give any.
+pub(crate) fn get_openapi_operation(
+    spec: &serde_json::Value,
+    base_url: &str,
+    route: &str,
+    method: &str,
+    item: &serde_json::Value,
+    operation: &serde_json::Value,
+) -> ScenarioStep {
+    let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes()).unwrap();
+    let mut url = format!("{0}{1}", base_url.trim_end_matches('/'), route);
+    let mut query = vec![];
+    let mut headers = vec![];
+
+    // The path item's parameters apply to each of its operations.
+    let parameters = item["parameters"]
+        .as_array()
+        .into_iter()
+        .chain(operation["parameters"].as_array())
+        .flatten()
+        .map(|p| resolve_ref(spec, p));
+    for parameter in parameters {
+        let Some(name) = parameter["name"].as_str() else {
+            continue;
+        };
+        let is_required = parameter["required"].as_bool().unwrap_or(false);
+        let value = || {
+            let example = parameter
+                .get("example")
+                .or_else(|| first_example(&parameter["examples"]))
+                .cloned()
+                .unwrap_or_else(|| get_schema_example(spec, &parameter["schema"], &[]));
+            match example {
+                serde_json::Value::String(value) => value,
+                example => example.to_string(),
+            }
+        };
+
+        match parameter["in"].as_str() {
+            Some("path") => url = url.replace(&format!("{{{0}}}", name), &percent_encode(&value())),
+            Some("query") if is_required => query.push(format!(
+                "{0}={1}",
+                percent_encode(name),
+                percent_encode(&value())
+            )),
+            Some("header") if is_required => headers.push((name.to_owned(), value())),
+            _ => {}
+        }
+    }
+    if !query.is_empty() {
+        url = format!("{0}?{1}", url, query.join("&"));
+    }
+
+    let request_body = resolve_ref(spec, &operation["requestBody"]);
+    let content = request_body["content"].as_object();
+    let json = content.and_then(|c| {
+        c.iter()
+            .find(|(content_type, _)| content_type.contains("json"))
+            .or_else(|| c.iter().next())
+    });
+    let body = json.and_then(|(content_type, media)| {
+        let example = media
+            .get("example")
+            .or_else(|| first_example(&media["examples"]))
+            .cloned();
+        let body = match example {
+            Some(serde_json::Value::String(example)) if !content_type.contains("json") => example,
+            Some(example) => example.to_string(),
+            None if content_type.contains("json") => {
+                get_schema_example(spec, &media["schema"], &[]).to_string()
+            }
+            None => return None,
+        };
+        headers.push((String::from("Content-Type"), content_type.clone()));
+        Some(body)
+    });
+
+    ScenarioStep {
+        name: operation["operationId"]
+            .as_str()
+            .map_or_else(|| format!("{0} {1}", method, route), String::from),
+        method,
+        url,
+        headers,
+        body,
+        extractions: vec![],
+        think_time: None,
+        retry_non_idempotent: false,
+    }
+}
+
+/// Gets the value of the first of an OpenAPI object's named examples, if it has any.
+pub(crate) fn first_example(examples: &serde_json::Value) -> Option<&serde_json::Value> {
+    examples.as_object()?.values().next()?.get("value")
+}
+
+/// Follows a reference to somewhere else in an OpenAPI spec (e.g. `{"$ref": "#/components/schemas/Order"}`), or gives
+/// the value itself if it isn't one.
+pub(crate) fn resolve_ref<'a>(
+    spec: &'a serde_json::Value,
+    value: &'a serde_json::Value,
+) -> &'a serde_json::Value {
+    let mut value = value;
+
+    for _ in 0..MAX_REF_CHAIN {
+        match value["$ref"].as_str().and_then(|r| r.strip_prefix('#')) {
+            Some(pointer) => value = spec.pointer(pointer).unwrap_or(&serde_json::Value::Null),
+            None => break,
+        }
+    }
+
+    value
+}
+
+/// Makes up an example that matches an OpenAPI schema, using the examples, defaults and enums in it where it has them.
+/// Schemas that refer to themselves (e.g. an item with a parent item) are followed once, with the references that are
+/// already being followed left out.
+pub(crate) fn get_schema_example<'a>(
+    spec: &'a serde_json::Value,
+    schema: &'a serde_json::Value,
+    followed: &[&'a str],
+) -> serde_json::Value {
+    let mut followed = followed.to_vec();
+    if let Some(reference) = schema["$ref"].as_str() {
+        if followed.contains(&reference) {
+            return serde_json::Value::Null;
+        }
+        followed.push(reference);
+    }
+
+    let schema = resolve_ref(spec, schema);
+    if let Some(example) = schema.get("example").or_else(|| schema.get("default")) {
+        return example.clone();
+    }
+    if let Some(value) = schema["enum"].get(0) {
+        return value.clone();
+    }
+    if let Some(schema) = schema["oneOf"].get(0).or_else(|| schema["anyOf"].get(0)) {
+        return get_schema_example(spec, schema, &followed);
+    }
+    if let Some(schemas) = schema["allOf"].as_array() {
+        let mut merged = serde_json::Map::new();
+        for schema in schemas {
+            if let serde_json::Value::Object(example) = get_schema_example(spec, schema, &followed)
+            {
+                merged.extend(example);
+            }
+        }
+        return serde_json::Value::Object(merged);
+    }
+
+    let kind = match &schema["type"] {
+        // OpenAPI 3.1 allows a list of types, e.g. ["string", "null"].
+        serde_json::Value::Array(kinds) => kinds
+            .iter()
+            .find_map(|k| k.as_str().filter(|k| *k != "null")),
+        kind => kind.as_str(),
+    };
+    match (kind, schema["format"].as_str()) {
+        (Some("object"), _) | (None, _) if schema.get("properties").is_some() => {
+            serde_json::Value::Object(
+                schema["properties"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, property)| {
+                        (name.clone(), get_schema_example(spec, property, &followed))
+                    })
+                    .filter(|(_, example)| !example.is_null())
+                    .collect(),
+            )
+        }
+        (Some("object"), _) => serde_json::json!({}),
+        (Some("array"), _) => {
+            serde_json::json!([get_schema_example(spec, &schema["items"], &followed)])
+        }
+        (Some("integer"), _) => serde_json::json!(schema["minimum"].as_i64().unwrap_or(1)),
+        (Some("number"), _) => serde_json::json!(schema["minimum"].as_f64().unwrap_or(1.0)),
+        (Some("boolean"), _) => serde_json::json!(true),
+        (Some("string"), Some("date-time")) => serde_json::json!("2024-01-01T00:00:00Z"),
+        (Some("string"), Some("date")) => serde_json::json!("2024-01-01"),
+        (Some("string"), Some("email")) => serde_json::json!("user@example.com"),
+        (Some("string"), Some("uuid")) => serde_json::json!("3fa85f64-5717-4562-b3fc-2c963f66afa6"),
+        (Some("string"), Some("uri" | "url")) => serde_json::json!("https://example.com"),
+        (Some("string"), _) => serde_json::json!("string"),
+        _ => serde_json::Value::Null,
+    }
+}
+
+/// Splits a command into its arguments as a POSIX shell would, handling single quotes, double quotes, `$'...'` quotes
+/// (as browsers copy curl commands with), backslash escapes and line continuations.
+pub(crate) fn split_shell_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => return Err(String::from("it ends with an unfinished escape")),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("it has an unclosed single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(String::from("it has an unclosed double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("it has an unclosed double quote")),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('r') => word.push('\r'),
+                            Some('t') => word.push('\t'),
+                            Some(c) => word.push(c),
+                            None => return Err(String::from("it has an unclosed $' quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(String::from("it has an unclosed $' quote")),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+/// Quotes words so that a POSIX shell (or [`split_shell_words`]) splits them back into the same words, single-quoting
+/// any that aren't made up only of characters that are safe unquoted.
+pub(crate) fn join_shell_words(words: &[String]) -> String {
+    words
+        .iter()
+        .map(|w| {
+            if !w.is_empty()
+                && w.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,%+".contains(c))
+            {
+                w.clone()
+            } else {
+                format!("'{0}'", w.replace('\'', r"'\''"))
+            }
+        })
+        .join(" ")
+}
+
+/// Percent-encodes text for a form, as curl's --data-urlencode does.
+pub(crate) fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{0:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_shell_words_handles_quotes_escapes_and_continuations() {
+        let words = split_shell_words(
+            "curl 'https://my-api.com/a b' \\\n  -H \"X-Name: \\\"jo\\\"\" --data-raw $'line\\none' plain\\ word",
+        )
+        .unwrap();
+
+        assert_eq!(
+            words,
+            [
+                "curl",
+                "https://my-api.com/a b",
+                "-H",
+                "X-Name: \"jo\"",
+                "--data-raw",
+                "line\none",
+                "plain word"
+            ]
+        );
+    }
+
+    #[test]
+    fn split_shell_words_rejects_unclosed_quotes() {
+        assert!(split_shell_words("curl 'https://my-api.com").is_err());
+        assert!(split_shell_words("curl \"https://my-api.com").is_err());
+        assert!(split_shell_words("curl https://my-api.com\\").is_err());
+    }
+
+    #[test]
+    fn parse_curl_command_reads_the_request() {
+        let curl = parse_curl_command(
+            "curl -sSL -X put 'https://my-api.com/users/1' -H 'Content-Type: application/json' \
+             -H 'X-Trace:  abc ' -d '{\"name\": \"jo\"}' -u jo:s3cret -b 'a=1; b=2' -k --http2",
+        )
+        .unwrap();
+
+        assert_eq!(curl.url, "https://my-api.com/users/1");
+        assert_eq!(curl.method, Method::PUT);
+        assert_eq!(
+            curl.headers,
+            [
+                (
+                    String::from("Content-Type"),
+                    String::from("application/json")
+                ),
+                (String::from("X-Trace"), String::from("abc")),
+            ]
+        );
+        assert_eq!(curl.body.as_deref(), Some(&b"{\"name\": \"jo\"}"[..]));
+        assert_eq!(
+            curl.basic_auth,
+            Some((String::from("jo"), String::from("s3cret")))
+        );
+        assert_eq!(
+            curl.cookies,
+            [
+                (String::from("a"), String::from("1")),
+                (String::from("b"), String::from("2")),
+            ]
+        );
+        assert!(curl.insecure);
+        assert!(curl.http_version == Some(HttpVersionPreference::Http2));
+    }
+
+    #[test]
+    fn parse_curl_command_posts_data_as_a_form_by_default() {
+        let curl = parse_curl_command("curl my-api.com/login -d user=jo --data-urlencode 'q=a b'")
+            .unwrap();
+
+        assert_eq!(curl.url, "http://my-api.com/login");
+        assert_eq!(curl.method, Method::POST);
+        assert_eq!(curl.body.as_deref(), Some(&b"user=jo&q=a%20b"[..]));
+        assert_eq!(
+            curl.headers,
+            [(
+                String::from("Content-Type"),
+                String::from("application/x-www-form-urlencoded")
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_curl_command_sends_data_as_the_query_with_get() {
+        let curl =
+            parse_curl_command("curl -G 'https://my-api.com/search?page=2' -d q=jo").unwrap();
+
+        assert_eq!(curl.url, "https://my-api.com/search?page=2&q=jo");
+        assert_eq!(curl.method, Method::GET);
+        assert!(curl.body.is_none());
+        assert_eq!(
+            parse_curl_command("curl -I https://my-api.com")
+                .unwrap()
+                .method,
+            Method::HEAD
+        );
+    }
+
+    #[test]
+    fn parse_curl_command_expands_combined_flags_but_not_values() {
+        let curl =
+            parse_curl_command("curl -skXPOST https://my-api.com -d -sk --compressed").unwrap();
+
+        assert!(curl.insecure);
+        assert!(curl.compressed);
+        assert_eq!(curl.method, Method::POST);
+        assert_eq!(curl.body.as_deref(), Some(&b"-sk"[..]));
+        assert!(parse_curl_command("curl -sZ https://my-api.com").is_err());
+    }
+
+    #[test]
+    fn parse_curl_command_reads_data_files_like_curl() {
+        let path = std::env::temp_dir().join(format!("smashit-{0}-data.bin", std::process::id()));
+        std::fs::write(&path, b"a=1\r\nb=2\n\xff").unwrap();
+        let path = path.to_string_lossy();
+
+        let curl = parse_curl_command(&format!("curl https://my-api.com -d @{0}", path)).unwrap();
+        assert_eq!(curl.body.as_deref(), Some(&b"a=1b=2\xff"[..]));
+
+        let curl = parse_curl_command(&format!("curl https://my-api.com --data-binary @{0}", path))
+            .unwrap();
+        assert_eq!(curl.body.as_deref(), Some(&b"a=1\r\nb=2\n\xff"[..]));
+    }
+
+    #[test]
+    fn parse_curl_command_rejects_what_it_cant_send() {
+        assert!(parse_curl_command("curl -s").is_err());
+        assert!(parse_curl_command("curl --upload-file a.txt https://my-api.com").is_err());
+        assert!(parse_curl_command("curl -H 'no colon' https://my-api.com").is_err());
+        assert!(parse_curl_command("curl -X").is_err());
+    }
+
+    #[test]
+    fn parse_har_file_turns_entries_into_steps() {
+        let path = std::env::temp_dir().join(format!("smashit-{0}-test.har", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"log": {"entries": [
+                {"request": {"method": "post", "url": "https://my-api.com/login",
+                    "headers": [{"name": ":authority", "value": "my-api.com"}, {"name": "Host", "value": "my-api.com"},
+                                {"name": "Content-Type", "value": "application/json"}],
+                    "postData": {"text": "{}"}}},
+                {"request": {"method": "GET", "url": "data:image/png;base64,AAAA"}},
+                {"request": {"method": "GET", "url": "https://my-api.com/me"}}
+            ]}}"#,
+        )
+        .unwrap();
+        let scenario = parse_har_file(&path.to_string_lossy()).unwrap();
+
+        assert_eq!(scenario.steps.len(), 2);
+        let login = &scenario.steps[0];
+        assert_eq!(login.name, "POST https://my-api.com/login");
+        assert_eq!(login.method, Method::POST);
+        assert_eq!(
+            login.headers,
+            [(
+                String::from("Content-Type"),
+                String::from("application/json")
+            )]
+        );
+        assert_eq!(login.body.as_deref(), Some("{}"));
+        assert_eq!(scenario.steps[1].url, "https://my-api.com/me");
+    }
+}
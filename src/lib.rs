@@ -0,0 +1,267 @@
+//! smashit - a simple, single machine, CLI-based HTTP load testing tool built whilst learning rust.
+//!
+//! Besides the command line tool, its runner can be embedded to load test from elsewhere, such as integration tests:
+//! a [`TestConfig`] is parsed from the same options as the command line takes, a [`LoadTest`] sets up the clients to
+//! send its requests with, and running it gives the [`TestResults`]. A [`LoadTestBuilder`] builds them up in code
+//! instead from typed options, giving the [`RunResults`], with a stream of every request's [`RequestEvent`], progress
+//! callbacks and a [`CancellationToken`].
+//!
+//! Embedded runs don't print anything as they go, unlike those started from the command line, leaving it to the
+//! builder's progress callbacks and request events to follow them.
+
+mod args;
+mod builder;
+mod distributed;
+mod import;
+mod record;
+mod report;
+mod runner;
+mod serve;
+mod stats;
+
+use std::process::ExitCode;
+
+pub use args::TestConfig;
+pub use builder::{LatencyStatistic, LoadTestBuilder, Progress, RequestEvent, RequestEvents};
+pub use reqwest::Method;
+pub use runner::LoadTest;
+pub use stats::{ResponsesTimings, RunResults, TestResults};
+pub use tokio_util::sync::CancellationToken;
+
+use crate::{args::*, distributed::*, record::*, report::*, runner::*, serve::*, stats::*};
+
+/// The exit code when a run's thresholds or baseline weren't met.
+pub(crate) const EXIT_CHECKS_FAILED: u8 = 1;
+
+/// The exit code when the options aren't valid, which shows the help.
+pub(crate) const EXIT_INVALID_ARGS: u8 = 2;
+
+/// The exit code when a run couldn't be performed (or a saved one reported on), every one of its requests failed, too
+/// many of them did and it was aborted or its results couldn't be saved or exported.
+pub(crate) const EXIT_RUN_FAILED: u8 = 3;
+
+/// The exit code when a run was stopped early by Ctrl+C, as shells give processes interrupted by SIGINT.
+pub(crate) const EXIT_INTERRUPTED: u8 = 130;
+
+/// Runs smashit as it is from the command line with the given arguments (the first being the program's name),
+/// printing everything as it goes and returning an exit code for how it went: success, or one of `EXIT_CHECKS_FAILED`,
+/// `EXIT_INVALID_ARGS`, `EXIT_RUN_FAILED` or `EXIT_INTERRUPTED`.
+pub async fn run_cli(raw_args: Vec<String>) -> ExitCode {
+    if raw_args.get(1).map(String::as_str) == Some("--help") {
+        show_help();
+        return ExitCode::SUCCESS;
+    }
+
+    // `smashit report run.json [--format text|json|html]` reports on a run saved with --save.
+    if raw_args.get(1).map(String::as_str) == Some("report") {
+        return match parse_report_args(&raw_args) {
+            Some((path, format)) => get_exit_code(report_saved_run(&path, format)),
+            None => show_invalid_args(),
+        };
+    }
+
+    // `smashit compare baseline.json candidate.json` compares two runs saved with --save.
+    if raw_args.get(1).map(String::as_str) == Some("compare") {
+        return match (raw_args.get(2), raw_args.get(3), raw_args.len()) {
+            (Some(baseline), Some(candidate), 4) => {
+                get_exit_code(compare_saved_runs(baseline, candidate))
+            }
+            _ => show_invalid_args(),
+        };
+    }
+
+    // The worker, API and recording proxy run until smashit is stopped, so they only return if they couldn't start or
+    // carry on listening.
+
+    // `smashit worker --token TOKEN [--listen :7000] [--cert worker.pem --key worker.key]` runs the plans sent by
+    // `smashit run --workers` with its token, over TLS if it's given a certificate.
+    if raw_args.get(1).map(String::as_str) == Some("worker") {
+        return match parse_listen_args(&raw_args, DEFAULT_WORKER_PORT) {
+            Some(ListenArgs {
+                address,
+                token: Some(token),
+                tls,
+            }) => {
+                let tls = match tls.map(|(cert, key)| get_worker_tls_acceptor(&cert, &key)) {
+                    Some(Ok(tls)) => Some(tls),
+                    Some(Err(e)) => {
+                        eprintln!("Failed to read the worker's certificate and key: {0}", e);
+                        return ExitCode::from(EXIT_INVALID_ARGS);
+                    }
+                    None => None,
+                };
+                run_worker(address, token, tls).await;
+                ExitCode::from(EXIT_RUN_FAILED)
+            }
+            _ => show_invalid_args(),
+        };
+    }
+
+    // `smashit serve [--listen :8080] [--token TOKEN]` serves an API for starting runs and following their progress,
+    // which has to be given a token to serve it to the rest of the network.
+    if raw_args.get(1).map(String::as_str) == Some("serve") {
+        return match parse_listen_args(&raw_args, DEFAULT_SERVE_PORT) {
+            Some(ListenArgs {
+                address,
+                token,
+                tls: None,
+            }) if token.is_some() || address.ip().is_loopback() => {
+                serve_api(address, token).await;
+                ExitCode::from(EXIT_RUN_FAILED)
+            }
+            Some(ListenArgs {
+                address, tls: None, ..
+            }) => {
+                eprintln!(
+                    "The API needs a --token for its requests to send to be served on {0}, as anyone who can reach it \
+                     could otherwise start runs from this machine",
+                    address
+                );
+                ExitCode::from(EXIT_INVALID_ARGS)
+            }
+            _ => show_invalid_args(),
+        };
+    }
+
+    // `smashit record --out scenario.yaml [--listen :8888]` records the requests made through a proxy into a scenario.
+    if raw_args.get(1).map(String::as_str) == Some("record") {
+        return match parse_record_args(&raw_args) {
+            Some((address, path)) => {
+                run_recording_proxy(address, path).await;
+                ExitCode::from(EXIT_RUN_FAILED)
+            }
+            None => show_invalid_args(),
+        };
+    }
+
+    let Some(config) = TestConfig::parse(raw_args.clone()) else {
+        return show_invalid_args();
+    };
+
+    println!("\nsmashit - a simple, single machine, CLI-based HTTP load testing tool built whilst learning rust\n");
+
+    if !config.args.workers.is_empty() {
+        let args = &config.args;
+        let Some(baseline) = read_baseline(args) else {
+            return ExitCode::from(EXIT_INVALID_ARGS);
+        };
+
+        print_request_summary(args);
+
+        return match get_worker_results(&raw_args, args).await {
+            Ok((results, state)) => {
+                finish_run(&raw_args, &results, &state, args, baseline.as_ref())
+            }
+            Err(e) => {
+                eprintln!("{0}", e);
+                ExitCode::from(EXIT_RUN_FAILED)
+            }
+        };
+    }
+
+    let load_test = match LoadTest::new(config).await {
+        Ok(load_test) => load_test,
+        Err(e) => {
+            eprintln!("{0}", e);
+            return ExitCode::from(EXIT_RUN_FAILED);
+        }
+    };
+
+    // The baseline is read before the run so that it doesn't go to waste if it can't be.
+    let Some(baseline) = read_baseline(&load_test.args) else {
+        return ExitCode::from(EXIT_INVALID_ARGS);
+    };
+
+    print_request_summary(&load_test.args);
+
+    // Interrupting the run stops it early, with whatever completed until then still reported on.
+    let (mut progress, mut interrupt) = (None, None);
+    let test_results = load_test.run_watched(|state| {
+        progress = Some(print_progress(state.clone(), load_test.args.clone()));
+        interrupt = Some(tokio::spawn(stop_on_interrupt(state)));
+    });
+    let test_results = match test_results.await {
+        Ok(test_results) => test_results,
+        Err(e) => {
+            eprintln!("{0}", e);
+            return ExitCode::from(EXIT_RUN_FAILED);
+        }
+    };
+    for task in progress.into_iter().chain(interrupt) {
+        task.abort();
+    }
+    finish_run(
+        &raw_args,
+        &test_results.results,
+        &test_results.state,
+        &load_test.args,
+        baseline.as_ref(),
+    )
+}
+
+/// Shows the help for options that aren't valid, giving the exit code for them.
+fn show_invalid_args() -> ExitCode {
+    show_help();
+    ExitCode::from(EXIT_INVALID_ARGS)
+}
+
+/// Gets the exit code for whether something other than a run (such as reporting on a saved one) could be done.
+fn get_exit_code(succeeded: bool) -> ExitCode {
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_RUN_FAILED)
+    }
+}
+
+/// Reads the run to compare against if a baseline was given, which is None if it can't be read (having printed why).
+fn read_baseline(args: &ParsedArgs) -> Option<Option<SavedRun<'static>>> {
+    match &args.baseline {
+        Some(path) => read_saved_run(path).map(Some),
+        None => Some(None),
+    }
+}
+
+/// Prints, exports and saves the results of a finished run, returning a failure if it was stopped early, every one of
+/// its requests failed, its results couldn't be exported or saved or any of its thresholds or its baseline weren't met.
+fn finish_run(
+    raw_args: &[String],
+    results: &[ResponseStatistics],
+    state: &RunState,
+    args: &ParsedArgs,
+    baseline: Option<&SavedRun>,
+) -> ExitCode {
+    print_results(results, state, args);
+
+    let mut written = true;
+    for (format, path) in &args.exports {
+        if let Err(e) = export_results(format, path, results, state, args) {
+            eprintln!("\nFailed to export results to {0}: {1}", path, e);
+            written = false;
+        }
+    }
+
+    if let Some(path) = &args.save_path {
+        if let Err(e) = save_run(path, raw_args, results, state) {
+            eprintln!("\nFailed to save the run to {0}: {1}", path, e);
+            written = false;
+        }
+    }
+
+    let thresholds_passed = check_thresholds(results, args);
+    let baseline_passed = check_baseline(results, state, args, baseline);
+    match state.stop_reason() {
+        Some(StopReason::Interrupted | StopReason::Cancelled) => {
+            return ExitCode::from(EXIT_INTERRUPTED)
+        }
+        Some(StopReason::Errors) => return ExitCode::from(EXIT_RUN_FAILED),
+        None => {}
+    }
+    if !written || !results.iter().any(|r| r.is_success) {
+        ExitCode::from(EXIT_RUN_FAILED)
+    } else if thresholds_passed && baseline_passed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_CHECKS_FAILED)
+    }
+}
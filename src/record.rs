@@ -0,0 +1,287 @@
+//! Recording the requests that clients (such as a browser or the app in front of an API) send through a proxy into a
+//! scenario file, so that load tests can be built from real traffic rather than written by hand.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    client::HttpConnector,
+    header::{CONNECTION, CONTENT_LENGTH, COOKIE, HOST},
+    http::request::Parts,
+    service::{make_service_fn, service_fn},
+    Body, Client, HeaderMap, Method, Request, Response, Server, StatusCode,
+};
+use tokio::net::TcpStream;
+
+use crate::args::*;
+
+/// The port that the recording proxy listens on unless it's told otherwise.
+pub(crate) const DEFAULT_RECORD_PORT: u16 = 8888;
+
+/// The headers that only apply to a client's (or server's) connection with the proxy, which are neither forwarded nor
+/// recorded, along with any that the Connection header names.
+pub(crate) const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Represents a recording in progress, being the steps recorded so far and where they're saved.
+pub(crate) struct Recording {
+    pub(crate) path: String,
+    pub(crate) file: Mutex<ScenarioFile>,
+    /// The hosts that have been tunnelled to, which have already been reported as not being recorded.
+    pub(crate) tunnelled: Mutex<HashSet<String>>,
+    pub(crate) client: Client<HttpConnector>,
+}
+
+/// Runs a forward proxy until smashit is stopped, recording each request made through it as a step of the scenario
+/// at the path. HTTPS requests are tunnelled through it without being recorded, as they're encrypted.
+pub(crate) async fn run_recording_proxy(address: SocketAddr, path: String) {
+    let recording = Arc::new(Recording {
+        path,
+        file: Mutex::new(ScenarioFile {
+            steps: vec![],
+            rate: None,
+            think_time: None,
+        }),
+        tunnelled: Mutex::default(),
+        client: Client::new(),
+    });
+    let make_service = {
+        let recording = recording.clone();
+        make_service_fn(move |_| {
+            let recording = recording.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle_proxied_request(request, recording.clone())
+                }))
+            }
+        })
+    };
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            eprintln!(
+                "Failed to listen for requests to record on {0}: {1}",
+                address, e
+            );
+            return;
+        }
+    };
+    println!(
+        "🎙️ Recording the requests made through the proxy on {0} into {1}\n",
+        address, recording.path
+    );
+
+    if let Err(e) = server.await {
+        eprintln!("Failed to keep recording requests: {0}", e);
+    }
+}
+
+/// Handles a request made through the proxy, forwarding it on to its server and recording it, or tunnelling it if
+/// it's a CONNECT.
+pub(crate) async fn handle_proxied_request(
+    request: Request<Body>,
+    recording: Arc<Recording>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() == Method::CONNECT {
+        return Ok(tunnel_request(request, &recording));
+    }
+
+    // Clients send the whole URL to proxies, whereas a path alone means the client is treating it as the server.
+    if request.uri().scheme().is_none() {
+        return Ok(get_proxy_error_response(
+            StatusCode::BAD_REQUEST,
+            "smashit record is a proxy, so requests need to be sent through it rather than to it",
+        ));
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            return Ok(get_proxy_error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Failed to read the request's body: {0}", e),
+            ))
+        }
+    };
+    remove_hop_by_hop_headers(&mut parts.headers);
+    let step = get_recorded_step(&parts, &body);
+
+    let request = Request::from_parts(parts, Body::from(body));
+    let response = match recording.client.request(request).await {
+        Ok(mut response) => {
+            println!(
+                "\t{0} {1} → {2}",
+                step.method.as_deref().unwrap_or("GET"),
+                step.url,
+                response.status()
+            );
+            remove_hop_by_hop_headers(response.headers_mut());
+            response
+        }
+        Err(e) => {
+            println!(
+                "\t{0} {1} → {2}",
+                step.method.as_deref().unwrap_or("GET"),
+                step.url,
+                e
+            );
+            get_proxy_error_response(
+                StatusCode::BAD_GATEWAY,
+                &format!("Failed to forward the request: {0}", e),
+            )
+        }
+    };
+
+    recording.record(step);
+    Ok(response)
+}
+
+/// Gets the scenario step that replays a request made through the proxy.
+pub(crate) fn get_recorded_step(parts: &Parts, body: &[u8]) -> ScenarioFileStep {
+    let url = parts.uri.to_string();
+
+    // Headers that are given more than once are recorded as one, as they'd be combined if they were sent that way.
+    let mut headers = BTreeMap::<String, String>::new();
+    for (name, value) in &parts.headers {
+        if name == HOST || name == CONTENT_LENGTH {
+            continue;
+        }
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        let separator = if name == COOKIE { "; " } else { ", " };
+        headers
+            .entry(name.to_string())
+            .and_modify(|v| {
+                v.push_str(separator);
+                v.push_str(value);
+            })
+            .or_insert_with(|| value.to_owned());
+    }
+
+    let body = match std::str::from_utf8(body) {
+        _ if body.is_empty() => None,
+        Ok(body) => Some(body.to_owned()),
+        Err(_) => {
+            eprintln!(
+                "\tThe body of {0} {1} isn't text, so it has been recorded without it",
+                parts.method, url
+            );
+            None
+        }
+    };
+
+    ScenarioFileStep {
+        name: None,
+        method: (parts.method != Method::GET).then(|| parts.method.to_string()),
+        url,
+        headers,
+        body,
+        extract: HashMap::new(),
+        think_time: None,
+        retry_non_idempotent: false,
+    }
+}
+
+impl Recording {
+    /// Records a step, saving the scenario with it straight away so that nothing is lost when smashit is stopped.
+    pub(crate) fn record(&self, step: ScenarioFileStep) {
+        let mut file = self.file.lock().unwrap();
+        file.steps.push(step);
+
+        let is_yaml = self.path.ends_with(".yaml") || self.path.ends_with(".yml");
+        let contents = if is_yaml {
+            serde_yaml::to_string(&*file).map_err(|e| e.to_string())
+        } else {
+            serde_json::to_string_pretty(&*file).map_err(|e| e.to_string())
+        };
+        if let Err(e) =
+            contents.and_then(|c| std::fs::write(&self.path, c).map_err(|e| e.to_string()))
+        {
+            eprintln!("\tFailed to save the scenario to {0}: {1}", self.path, e);
+        }
+    }
+}
+
+/// Tunnels a CONNECT request (which HTTPS requests are made through proxies with) to its server once the client has
+/// upgraded its connection, without recording what's sent through it.
+pub(crate) fn tunnel_request(request: Request<Body>, recording: &Recording) -> Response<Body> {
+    let Some(authority) = request.uri().authority().map(|a| a.to_string()) else {
+        return get_proxy_error_response(
+            StatusCode::BAD_REQUEST,
+            "CONNECT requests need to be to a host and port",
+        );
+    };
+    if recording
+        .tunnelled
+        .lock()
+        .unwrap()
+        .insert(authority.clone())
+    {
+        println!(
+            "\tCONNECT {0} → tunnelled without being recorded, as HTTPS requests are encrypted",
+            authority
+        );
+    }
+
+    tokio::spawn(async move {
+        let tunnel = async {
+            let mut upgraded = hyper::upgrade::on(request)
+                .await
+                .map_err(|e| e.to_string())?;
+            let mut server = TcpStream::connect(&authority)
+                .await
+                .map_err(|e| e.to_string())?;
+            tokio::io::copy_bidirectional(&mut upgraded, &mut server)
+                .await
+                .map_err(|e| e.to_string())
+        };
+        if let Err(e) = tunnel.await {
+            eprintln!("\tFailed to tunnel to {0}: {1}", authority, e);
+        }
+    });
+
+    Response::new(Body::empty())
+}
+
+/// Removes the headers that only apply to one connection with the proxy, which aren't for its other side.
+pub(crate) fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let named = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|h| h.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    for header in HOP_BY_HOP_HEADERS
+        .iter()
+        .copied()
+        .chain(named.iter().map(String::as_str))
+    {
+        headers.remove(header);
+    }
+}
+
+/// Builds a response from the proxy itself describing what went wrong.
+pub(crate) fn get_proxy_error_response(status: StatusCode, error: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(format!("{0}\n", error)))
+        .unwrap()
+}
@@ -0,0 +1,3953 @@
+//! Sending the requests of a run, from setting up the clients and connections they go through to pacing them.
+
+use std::{
+    borrow::Cow,
+    cell::OnceCell,
+    collections::{HashMap, HashSet},
+    fs::File,
+    future::Future,
+    io::{BufWriter, Read, Write},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use flate2::{
+    read::{GzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use hyper::{
+    body::HttpBody,
+    client::connect::{dns::Name, Connect, Connected, Connection, HttpInfo},
+};
+use itertools::Itertools;
+use rand::Rng;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    header::{
+        HeaderMap, HeaderValue, ACCEPT, CONTENT_ENCODING, CONTENT_TYPE, COOKIE, DATE, LINK,
+        SET_COOKIE,
+    },
+    Method, ResponseBuilderExt, StatusCode, Url, Version,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{mpsc::UnboundedSender, watch, Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+use tokio_native_tls::TlsStream;
+
+use crate::{args::*, builder::*, import::*, report::*, stats::*};
+
+/// The longest to wait for each of the requests that check whether a server supports HTTP/3.
+#[cfg(feature = "http3")]
+pub(crate) const HTTP3_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Represents the values available to the placeholders of a single request.
+pub(crate) struct TemplateContext<'a> {
+    /// The names of the data file's columns and their values in the row picked for the request, if there is one.
+    pub(crate) columns: &'a [String],
+    pub(crate) row: &'a [String],
+    /// The request's place in the run's sequence of requests, starting from 1.
+    pub(crate) seq: usize,
+    /// The variables extracted from the responses to the earlier steps of the request's scenario iteration.
+    pub(crate) variables: HashMap<String, String>,
+}
+
+/// The deepest that nested sitemap indexes will be followed when discovering the URLs in a sitemap.
+pub(crate) const MAX_SITEMAP_DEPTH: usize = 5;
+
+/// Represents state shared between the request dispatcher and anything that can influence or annotate a run whilst it
+/// is in progress.
+pub(crate) struct RunState {
+    pub(crate) started: Instant,
+    pub(crate) pattern: Mutex<Option<LoadPattern>>,
+    pub(crate) rate_multiplier: Mutex<f64>,
+    pub(crate) events: Mutex<Vec<RunEvent>>,
+    pub(crate) pacing_overruns: Mutex<Vec<Duration>>,
+    pub(crate) in_flight: AtomicUsize,
+    pub(crate) in_flight_peak: AtomicUsize,
+    pub(crate) in_flight_samples: Mutex<Vec<InFlightSample>>,
+    /// A client for each of the proxies being rotated between, in the same order.
+    pub(crate) proxy_clients: Vec<reqwest::Client>,
+    /// The client that sends requests over the Unix domain socket, if one was given.
+    #[cfg(unix)]
+    pub(crate) unix_client: Option<hyper::Client<UnixSocketConnector>>,
+    /// The client that sends requests over connections whose phases are timed, if phase timings were asked for.
+    pub(crate) timed_client: Option<hyper::Client<TimedConnector>>,
+    /// The connections whose phase timings have already been recorded against the request that opened them.
+    pub(crate) timed_connections: Mutex<HashSet<usize>>,
+    /// How long each DNS lookup took.
+    pub(crate) dns_lookups: Arc<Mutex<Vec<Duration>>>,
+    pub(crate) completed: AtomicUsize,
+    pub(crate) failed: AtomicUsize,
+    /// The response times of the requests completed since progress was last reported, if they got a response.
+    pub(crate) recently_completed: Mutex<Vec<Option<Duration>>>,
+    pub(crate) metrics: Mutex<Metrics>,
+    /// How many rows of the data file have been used, so that the next one can be picked in turn.
+    pub(crate) data_rows_used: AtomicUsize,
+    /// How many requests have been given a place in the sequence, for the `{{seq}}` placeholder, which carries on from
+    /// those sent whilst warming up so that no two requests share a place.
+    pub(crate) sequence: Arc<AtomicUsize>,
+    /// Roughly how long it took to set up an HTTP/3 connection to the server, if requests are being sent over HTTP/3.
+    pub(crate) http3_handshake: Option<Duration>,
+    /// The connections that can be open at once across all hosts, if they're limited.
+    pub(crate) connection_slots: Option<Arc<Semaphore>>,
+    /// The connections that can be open at once to each host (by its origin) that's been sent requests, if they're
+    /// limited.
+    pub(crate) host_connection_slots: Mutex<HashMap<String, Arc<Semaphore>>>,
+    /// The OAuth2 access token sent with every request, if there's an OAuth2 client to fetch them for.
+    pub(crate) oauth2_token: Option<Arc<AsyncMutex<OAuth2Token>>>,
+    /// When and why the run was stopped early, after which no more requests are sent, or none if it hasn't been.
+    pub(crate) stopped: watch::Sender<Option<(Instant, StopReason)>>,
+    /// The file that a line is written to for each request as it completes, if one was given.
+    pub(crate) request_log: Option<Arc<Mutex<BufWriter<File>>>>,
+    /// Where each request's result is sent as it completes, if the run was built in code and its results are being
+    /// streamed, which is let go of once the run is over so that the stream ends.
+    pub(crate) request_events: Mutex<Option<UnboundedSender<RequestEvent>>>,
+    /// The scenario that each iteration performs the steps of, if there is one, which is swapped for its file's new
+    /// contents whenever the file is reloaded.
+    pub(crate) scenario: Mutex<Option<Arc<Scenario>>>,
+    /// How many requests have been picked to be printed in full by --debug.
+    pub(crate) debugged: AtomicUsize,
+    /// The start of the distinct bodies of the responses to failed requests, in the order they were first seen.
+    pub(crate) failure_bodies: Mutex<Vec<FailureBody>>,
+    /// The requests that lost a hedged race, which are left to complete in the background (for a while) so that the
+    /// response time they'd have had without hedging is known without holding up whatever sent them.
+    pub(crate) hedge_losers: Mutex<Vec<JoinHandle<()>>>,
+    /// The response times of the requests that lost a hedged race, by their index in `hedge_losers`.
+    pub(crate) unhedged_response_times: Mutex<HashMap<usize, Duration>>,
+    /// How many requests were sent whilst warming up before the run, and over how long, if it was warmed up.
+    pub(crate) warmed_up: Option<(usize, Duration)>,
+    /// Whether events are printed as they're recorded, as they are when the run was started from the command line.
+    pub(crate) printing: AtomicBool,
+    /// The permits for the requests allowed in flight at once, if the run's concurrency is limited, which live controls
+    /// add to and take away from.
+    pub(crate) concurrency: Mutex<Option<Arc<Semaphore>>>,
+    /// How many requests (or virtual users) the run allows at once, as live controls have adjusted it.
+    pub(crate) concurrency_limit: AtomicUsize,
+    /// The virtual users that live controls have added since the run started, or none once the run is over and no more
+    /// can be.
+    pub(crate) added_virtual_users: Mutex<Option<Vec<JoinHandle<Vec<ResponseStatistics>>>>>,
+    /// How many virtual users live controls have asked to stop, each of which is taken by the next virtual user to start
+    /// an iteration, stopping it instead.
+    pub(crate) virtual_users_to_stop: AtomicUsize,
+}
+
+/// Represents the start of a body that the responses to failed requests were given, along with how many were.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct FailureBody {
+    pub(crate) status: u16,
+    pub(crate) body: String,
+    pub(crate) count: usize,
+}
+
+/// How many characters of the bodies of the responses to failed requests are kept, which is enough to see what the
+/// server said went wrong.
+pub(crate) const FAILURE_BODY_SAMPLE_LENGTH: usize = 200;
+
+/// How many distinct bodies of the responses to failed requests are kept, after which any others aren't.
+pub(crate) const MAX_FAILURE_BODY_SAMPLES: usize = 5;
+
+/// The most of a body that --debug prints, as responses can be far longer than is useful to see.
+pub(crate) const DEBUG_BODY_LIMIT: usize = 2048;
+
+/// Represents why a run was stopped before it finished.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum StopReason {
+    /// Ctrl+C was pressed.
+    Interrupted,
+    /// Too many of its requests failed, as given by --abort-on-errors.
+    Errors,
+    /// The cancellation token that its load test was built with was cancelled.
+    Cancelled,
+}
+
+/// How long the requests in flight when a run is stopped early have to complete before they're abandoned.
+pub(crate) const STOP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How many requests need to have completed before a percentage of them failing aborts the run, so that the first
+/// few failing alone don't.
+pub(crate) const MIN_ABORT_REQUESTS: usize = 20;
+
+/// Represents an OAuth2 access token, along with when to fetch a new one to replace it.
+pub(crate) struct OAuth2Token {
+    pub(crate) access_token: String,
+    /// When to fetch a new token, being once 90% of the token's lifetime has passed, or never if its token endpoint
+    /// didn't say when it expires.
+    pub(crate) refresh_at: Option<Instant>,
+}
+
+/// Represents a token endpoint's response to the client credentials grant.
+#[derive(Deserialize)]
+pub(crate) struct OAuth2TokenResponse {
+    pub(crate) access_token: String,
+    /// How many seconds the token is valid for.
+    pub(crate) expires_in: Option<u64>,
+}
+
+/// How long to wait before trying to fetch a new access token again after failing to during a run.
+pub(crate) const OAUTH2_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Represents the running totals exposed to Prometheus whilst a run is in progress.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// The number of completed requests by their status code, or `none` if they didn't get a response.
+    pub(crate) requests: HashMap<String, u64>,
+    /// The number of response times within each of the histogram's buckets, cumulatively as Prometheus expects.
+    pub(crate) latency_buckets: [u64; METRICS_LATENCY_BUCKETS.len()],
+    pub(crate) latency_sum: f64,
+    pub(crate) latency_count: u64,
+}
+
+/// The upper bounds, in seconds, of the buckets in the Prometheus response time histogram, which are Prometheus'
+/// defaults.
+pub(crate) const METRICS_LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Represents the number of requests in flight at a point in time during a run, along with the most that were in
+/// flight at once since the previous sample.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct InFlightSample {
+    pub(crate) at: Duration,
+    pub(crate) in_flight: usize,
+    pub(crate) peak: usize,
+}
+
+/// How often the number of requests in flight is sampled for the timeline.
+pub(crate) const IN_FLIGHT_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Marks a request as being in flight for as long as it is alive.
+pub(crate) struct InFlightRequest<'a> {
+    pub(crate) state: &'a RunState,
+}
+
+/// Represents something notable that happened at a point in time during a run, such as the load being adjusted.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RunEvent {
+    pub(crate) at: Duration,
+    pub(crate) description: String,
+}
+
+/// The longest time to keep waiting in the background for the request that lost a hedged race so that its latency can
+/// be compared with the winner's.
+pub(crate) const HEDGE_LOSER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sets up the state of a run, sharing the clients (and so the connections they have open) with any other runs.
+pub(crate) fn get_run_state(
+    args: &ParsedArgs,
+    proxy_clients: &[reqwest::Client],
+    #[cfg(unix)] unix_client: &Option<hyper::Client<UnixSocketConnector>>,
+    timed_client: &Option<hyper::Client<TimedConnector>>,
+    dns_lookups: &Arc<Mutex<Vec<Duration>>>,
+    http3_handshake: Option<Duration>,
+    oauth2_token: &Option<Arc<AsyncMutex<OAuth2Token>>>,
+) -> RunState {
+    RunState {
+        started: Instant::now(),
+        pattern: Mutex::new(args.pattern.clone()),
+        rate_multiplier: Mutex::new(1.0),
+        events: Mutex::new(vec![]),
+        pacing_overruns: Mutex::new(vec![]),
+        in_flight: AtomicUsize::new(0),
+        in_flight_peak: AtomicUsize::new(0),
+        in_flight_samples: Mutex::new(vec![]),
+        proxy_clients: proxy_clients.to_vec(),
+        #[cfg(unix)]
+        unix_client: unix_client.clone(),
+        timed_client: timed_client.clone(),
+        timed_connections: Mutex::new(HashSet::new()),
+        dns_lookups: dns_lookups.clone(),
+        completed: AtomicUsize::new(0),
+        failed: AtomicUsize::new(0),
+        recently_completed: Mutex::new(vec![]),
+        metrics: Mutex::new(Metrics::default()),
+        data_rows_used: AtomicUsize::new(0),
+        sequence: Arc::new(AtomicUsize::new(0)),
+        connection_slots: args.max_connections.map(|m| Arc::new(Semaphore::new(m))),
+        host_connection_slots: Mutex::new(HashMap::new()),
+        http3_handshake,
+        oauth2_token: oauth2_token.clone(),
+        stopped: watch::Sender::new(None),
+        request_log: None,
+        request_events: Mutex::new(None),
+        scenario: Mutex::new(args.scenario.clone().map(Arc::new)),
+        debugged: AtomicUsize::new(0),
+        failure_bodies: Mutex::new(vec![]),
+        hedge_losers: Mutex::new(vec![]),
+        unhedged_response_times: Mutex::new(HashMap::new()),
+        warmed_up: None,
+        printing: AtomicBool::new(false),
+        concurrency: Mutex::new(None),
+        concurrency_limit: AtomicUsize::new(args.virtual_users.or(args.concurrency).unwrap_or(0)),
+        added_virtual_users: Mutex::new(Some(vec![])),
+        virtual_users_to_stop: AtomicUsize::new(0),
+    }
+}
+
+/// Sets up the client that sends requests over the Unix domain socket, if one was given.
+#[cfg(unix)]
+pub(crate) fn get_unix_client(args: &ParsedArgs) -> Option<hyper::Client<UnixSocketConnector>> {
+    args.unix_socket.as_ref().map(|path| {
+        let mut builder = hyper::Client::builder();
+        if args.no_keepalive {
+            builder.pool_max_idle_per_host(0);
+        }
+        builder
+            .http2_only(args.http_version == HttpVersionPreference::Http2)
+            .build(UnixSocketConnector {
+                path: Arc::from(path.as_str()),
+            })
+    })
+}
+
+/// Sets up the client that sends requests over connections whose phases are timed, if phase timings were asked for,
+/// with the same TLS options and resolver as reqwest's.
+pub(crate) fn get_timed_client(
+    args: &ParsedArgs,
+    dns_lookups: &Arc<Mutex<Vec<Duration>>>,
+) -> Result<Option<hyper::Client<TimedConnector>>, String> {
+    // gRPC calls are sent over smashit's own connections too, as their statuses are in trailers that reqwest can't read.
+    if !args.phase_timings && args.grpc.is_none() {
+        return Ok(None);
+    }
+
+    let mut tls = native_tls::TlsConnector::builder();
+    tls.danger_accept_invalid_certs(args.insecure);
+    tls.request_alpns(match args.http_version {
+        HttpVersionPreference::Http1 => &["http/1.1"],
+        HttpVersionPreference::Http2 => &["h2"],
+        _ => &["h2", "http/1.1"],
+    });
+    if let Some(path) = &args.ca_cert_path {
+        let pem = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        for cert in get_pem_blocks(&pem, "CERTIFICATE").split_inclusive("-----END CERTIFICATE-----")
+        {
+            if !cert.trim().is_empty() {
+                tls.add_root_certificate(
+                    native_tls::Certificate::from_pem(cert.as_bytes())
+                        .map_err(|e| e.to_string())?,
+                );
+            }
+        }
+    }
+    if let Some(cert_path) = &args.client_cert_path {
+        let key_path = args.client_key_path.as_ref().unwrap_or(cert_path);
+        let (certs, key) = read_identity_pems(cert_path, key_path)?;
+        tls.identity(
+            native_tls::Identity::from_pkcs8(certs.as_bytes(), key.as_bytes())
+                .map_err(|e| e.to_string())?,
+        );
+    }
+
+    let connector = TimedConnector {
+        resolver: Arc::new(get_resolver(args, dns_lookups)),
+        overrides: Arc::new(
+            args.resolve
+                .iter()
+                .map(|(host, address)| ((host.clone(), address.port()), address.ip()))
+                .collect(),
+        ),
+        tls: tls.build().map_err(|e| e.to_string())?.into(),
+        opened: Arc::new(AtomicUsize::new(0)),
+    };
+
+    let mut builder = hyper::Client::builder();
+    if args.no_keepalive {
+        builder.pool_max_idle_per_host(0);
+    } else if let Some(max) = args.max_connections_per_host {
+        builder.pool_max_idle_per_host(max);
+    }
+    Ok(Some(
+        builder
+            .http2_only(args.http_version == HttpVersionPreference::Http2)
+            .build(connector),
+    ))
+}
+
+/// Sends requests before the run starts, with its concurrency (or one at a time), until the warm-up's duration has
+/// passed or its number of requests have been sent, returning how many were. Their results are thrown away.
+pub(crate) async fn warm_up(
+    client: &Arc<reqwest::Client>,
+    args: &Arc<ParsedArgs>,
+    state: &Arc<RunState>,
+) -> usize {
+    let workers = args.concurrency.or(args.virtual_users).unwrap_or(1);
+    let sent = Arc::new(AtomicUsize::new(0));
+
+    let warming_up = (0..workers).map(|_| {
+        let (client, args, state, sent) =
+            (client.clone(), args.clone(), state.clone(), sent.clone());
+        async move {
+            loop {
+                if args.warmup.is_some_and(|w| state.started.elapsed() >= w) {
+                    break;
+                }
+                let i = sent.fetch_add(1, Ordering::Relaxed);
+                if args.warmup_requests.is_some_and(|w| i >= w) {
+                    break;
+                }
+
+                let target = Target {
+                    proxy: get_proxy(&args, i, None),
+                    cookies: get_cookie_jar(&args),
+                    ..get_target(&args, i)
+                };
+                if args.scenario.is_some() {
+                    perform_scenario(client.clone(), args.clone(), state.clone(), target).await;
+                } else {
+                    perform_request_with_timeout(
+                        client.clone(),
+                        args.clone(),
+                        state.clone(),
+                        target,
+                    )
+                    .await;
+                }
+            }
+        }
+    });
+    futures::future::join_all(warming_up).await;
+
+    args.warmup_requests
+        .map_or(sent.load(Ordering::Relaxed), |w| {
+            w.min(sent.load(Ordering::Relaxed))
+        })
+}
+
+/// Builds a client that sends all of its requests through each of the proxies.
+pub(crate) fn get_proxy_clients(
+    args: &ParsedArgs,
+    dns_lookups: &Arc<Mutex<Vec<Duration>>>,
+) -> reqwest::Result<Vec<reqwest::Client>> {
+    args.proxies
+        .iter()
+        .map(|p| {
+            get_client_builder(args, dns_lookups)
+                .proxy(reqwest::Proxy::all(p)?)
+                .build()
+        })
+        .collect()
+}
+
+/// Gets a builder for the clients that send requests, set up to use the chosen HTTP version and TLS options, and to
+/// resolve names over DNS over HTTPS if a resolver was given, recording how long each lookup takes.
+pub(crate) fn get_client_builder(
+    args: &ParsedArgs,
+    dns_lookups: &Arc<Mutex<Vec<Duration>>>,
+) -> reqwest::ClientBuilder {
+    let mut builder = match args.http_version {
+        HttpVersionPreference::Negotiated => reqwest::Client::builder(),
+        HttpVersionPreference::Http1 => reqwest::Client::builder().http1_only(),
+        HttpVersionPreference::Http2 => reqwest::Client::builder().http2_prior_knowledge(),
+        #[cfg(feature = "http3")]
+        HttpVersionPreference::Http3 => reqwest::Client::builder()
+            .use_rustls_tls()
+            .http3_prior_knowledge(),
+    };
+
+    builder = builder.danger_accept_invalid_certs(args.insecure);
+    if args.no_keepalive {
+        builder = builder.pool_max_idle_per_host(0);
+    } else if let Some(max) = args.max_connections_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    for cert in &args.ca_certs {
+        builder = builder.add_root_certificate(cert.clone());
+    }
+    if let Some(identity) = &args.identity {
+        builder = builder.identity(identity.clone());
+    }
+
+    for (host, addr) in &args.resolve {
+        builder = builder.resolve(host, *addr);
+    }
+
+    builder.dns_resolver(Arc::new(get_resolver(args, dns_lookups)))
+}
+
+/// Represents each of the ways that names can be resolved, all of which record how long each lookup takes.
+pub(crate) enum Resolver {
+    System(SystemResolver),
+    Doh(DohResolver),
+    DnsServer(DnsServerResolver),
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        match self {
+            Resolver::System(resolver) => resolver.resolve(name),
+            Resolver::Doh(resolver) => resolver.resolve(name),
+            Resolver::DnsServer(resolver) => resolver.resolve(name),
+        }
+    }
+}
+
+/// Gets the resolver to look names up with: the DNS over HTTPS resolver or DNS server if one was given, otherwise the
+/// system's.
+pub(crate) fn get_resolver(args: &ParsedArgs, dns_lookups: &Arc<Mutex<Vec<Duration>>>) -> Resolver {
+    match (&args.doh, args.dns_server) {
+        (Some(url), _) => Resolver::Doh(DohResolver {
+            client: reqwest::Client::new(),
+            url: url.clone(),
+            lookups: dns_lookups.clone(),
+        }),
+        (None, Some(server)) => Resolver::DnsServer(DnsServerResolver {
+            server,
+            lookups: dns_lookups.clone(),
+        }),
+        (None, None) => Resolver::System(SystemResolver {
+            lookups: dns_lookups.clone(),
+        }),
+    }
+}
+
+/// Checks that the server responds over HTTP/3 if it's been asked for, as it can't be negotiated, returning the client
+/// (with its connection already set up, which concurrent requests can't race to do) and roughly how long the handshake
+/// took: the difference between the first request, which set up the connection, and the second. If the server doesn't
+/// respond, a warning is shown and requests negotiate the HTTP version instead.
+#[cfg(feature = "http3")]
+pub(crate) async fn check_http3(
+    args: &mut ParsedArgs,
+    dns_lookups: &Arc<Mutex<Vec<Duration>>>,
+) -> Option<(reqwest::Client, Duration)> {
+    if args.http_version != HttpVersionPreference::Http3 {
+        return None;
+    }
+
+    let url = args
+        .sitemap
+        .clone()
+        .or_else(|| Some(args.scenario.as_ref()?.steps[0].url.clone()))
+        .unwrap_or_else(|| get_target(args, 0).url);
+    let mut origin = Url::parse(&url).ok()?;
+    origin.set_path("/");
+    origin.set_query(None);
+
+    let client = get_client_builder(args, dns_lookups).build().ok()?;
+    let mut timings = vec![];
+    for _ in 0..2 {
+        let before_request = Instant::now();
+        let response = client
+            .get(origin.clone())
+            .version(Version::HTTP_3)
+            .timeout(HTTP3_CHECK_TIMEOUT)
+            .send()
+            .await;
+
+        if let Err(e) = response {
+            eprintln!(
+                "⚠️  {0} didn't respond over HTTP/3 ({1}), so requests will negotiate HTTP/1.1 or HTTP/2 instead",
+                redact_url(origin.as_str()),
+                redact_text(&e.to_string())
+            );
+            args.http_version = HttpVersionPreference::Negotiated;
+            return None;
+        }
+        timings.push(before_request.elapsed());
+    }
+
+    Some((client, timings[0].saturating_sub(timings[1])))
+}
+
+/// Connects to a Unix domain socket for every connection, whatever the host of the request.
+#[cfg(unix)]
+#[derive(Clone)]
+pub(crate) struct UnixSocketConnector {
+    pub(crate) path: Arc<str>,
+}
+
+#[cfg(unix)]
+impl hyper::service::Service<hyper::Uri> for UnixSocketConnector {
+    type Response = UnixSocketConnection;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = std::io::Result<UnixSocketConnection>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: hyper::Uri) -> Self::Future {
+        let path = self.path.clone();
+        Box::pin(async move { Ok(UnixSocketConnection(UnixStream::connect(&*path).await?)) })
+    }
+}
+
+/// A connection to a Unix domain socket that hyper can send requests over.
+#[cfg(unix)]
+pub(crate) struct UnixSocketConnection(UnixStream);
+
+#[cfg(unix)]
+impl Connection for UnixSocketConnection {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+#[cfg(unix)]
+impl AsyncRead for UnixSocketConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+#[cfg(unix)]
+impl AsyncWrite for UnixSocketConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Represents how long each phase of opening a connection took, which is attached to every response that comes over it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct ConnectionTimings {
+    /// Identifies the connection, as the order it was opened in.
+    pub(crate) id: usize,
+    pub(crate) local_addr: SocketAddr,
+    /// How long it took to look the host's name up, if it had to be.
+    pub(crate) dns: Option<Duration>,
+    pub(crate) connect: Duration,
+    /// How long the TLS handshake took, if the connection is over HTTPS.
+    pub(crate) tls: Option<Duration>,
+}
+
+/// Opens connections to the host of each request much as reqwest would, but timing each phase of doing so.
+#[derive(Clone)]
+pub(crate) struct TimedConnector {
+    pub(crate) resolver: Arc<Resolver>,
+    /// The addresses that hosts' names are pinned to for each port rather than being looked up.
+    pub(crate) overrides: Arc<HashMap<(String, u16), IpAddr>>,
+    pub(crate) tls: tokio_native_tls::TlsConnector,
+    /// How many connections have been opened, for identifying each of them.
+    pub(crate) opened: Arc<AtomicUsize>,
+}
+
+impl hyper::service::Service<hyper::Uri> for TimedConnector {
+    type Response = TimedConnection;
+    type Error = SendError;
+    type Future = Pin<Box<dyn Future<Output = Result<TimedConnection, SendError>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: hyper::Uri) -> Self::Future {
+        Box::pin(self.clone().connect(uri))
+    }
+}
+
+impl TimedConnector {
+    pub(crate) async fn connect(self, uri: hyper::Uri) -> Result<TimedConnection, SendError> {
+        let host = uri
+            .host()
+            .ok_or("the URL doesn't have a host")?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let is_https = uri.scheme_str() == Some("https");
+        let port = uri.port_u16().unwrap_or(if is_https { 443 } else { 80 });
+
+        let started = Instant::now();
+        let overridden = self.overrides.get(&(host.to_ascii_lowercase(), port));
+        let (addresses, dns) = match (host.parse::<IpAddr>(), overridden) {
+            (Ok(ip), _) => (vec![ip], None),
+            (_, Some(ip)) => (vec![*ip], None),
+            _ => {
+                let addresses = self
+                    .resolver
+                    .resolve(host.parse::<Name>()?)
+                    .await
+                    .map_err(|e| format!("dns error: {0}", e))?;
+                (
+                    addresses.map(|a| a.ip()).collect_vec(),
+                    Some(started.elapsed()),
+                )
+            }
+        };
+
+        // Each address is tried in turn until one of them accepts the connection.
+        let started = Instant::now();
+        let mut error: SendError = format!("dns error: no addresses found for {0}", host).into();
+        let mut stream = None;
+        for ip in addresses {
+            match TcpStream::connect((ip, port)).await {
+                Ok(s) => {
+                    stream = Some(s);
+                    break;
+                }
+                Err(e) => error = e.into(),
+            }
+        }
+        let stream = stream.ok_or(error)?;
+        let connect = started.elapsed();
+        stream.set_nodelay(true)?;
+
+        let mut timings = ConnectionTimings {
+            id: self.opened.fetch_add(1, Ordering::Relaxed),
+            local_addr: stream.local_addr()?,
+            dns,
+            connect,
+            tls: None,
+        };
+        if !is_https {
+            return Ok(TimedConnection {
+                stream: TimedStream::Plain(stream),
+                timings,
+                is_h2: false,
+            });
+        }
+
+        let started = Instant::now();
+        let stream = self.tls.connect(&host, stream).await?;
+        timings.tls = Some(started.elapsed());
+        let is_h2 = stream.get_ref().negotiated_alpn().ok().flatten().as_deref() == Some(b"h2");
+
+        Ok(TimedConnection {
+            stream: TimedStream::Tls(Box::new(stream)),
+            timings,
+            is_h2,
+        })
+    }
+}
+
+/// A connection opened by the timed connector, either over plain TCP or TLS.
+pub(crate) enum TimedStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+/// A connection that hyper can send requests over, which attaches how long it took to open to each response.
+pub(crate) struct TimedConnection {
+    pub(crate) stream: TimedStream,
+    pub(crate) timings: ConnectionTimings,
+    /// Whether HTTP/2 was negotiated with the server during the TLS handshake.
+    pub(crate) is_h2: bool,
+}
+
+impl Connection for TimedConnection {
+    fn connected(&self) -> Connected {
+        let connected = Connected::new().extra(self.timings);
+        if self.is_h2 {
+            connected.negotiated_h2()
+        } else {
+            connected
+        }
+    }
+}
+
+impl AsyncRead for TimedConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match &mut self.stream {
+            TimedStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            TimedStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for TimedConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match &mut self.stream {
+            TimedStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            TimedStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.stream {
+            TimedStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            TimedStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.stream {
+            TimedStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            TimedStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Resolves names by sending DNS queries in the RFC 8484 wire format to a DNS over HTTPS resolver.
+pub(crate) struct DohResolver {
+    pub(crate) client: reqwest::Client,
+    pub(crate) url: String,
+    pub(crate) lookups: Arc<Mutex<Vec<Duration>>>,
+}
+
+/// The DNS record types for IPv4 (A) and IPv6 (AAAA) addresses.
+pub(crate) const DNS_TYPE_A: u16 = 1;
+pub(crate) const DNS_TYPE_AAAA: u16 = 28;
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (client, url, lookups) = (self.client.clone(), self.url.clone(), self.lookups.clone());
+
+        Box::pin(async move {
+            let started = Instant::now();
+            let query = |record_type| {
+                client
+                    .post(&url)
+                    .header(CONTENT_TYPE, "application/dns-message")
+                    .header(ACCEPT, "application/dns-message")
+                    .body(get_dns_query(name.as_str(), record_type))
+                    .send()
+            };
+
+            let (a, aaaa) = futures::join!(query(DNS_TYPE_A), query(DNS_TYPE_AAAA));
+            let answers = async {
+                let mut addresses = vec![];
+                for response in [a, aaaa] {
+                    let body = response?.error_for_status()?.bytes().await?;
+                    addresses.extend(get_dns_answers(&body).unwrap_or_default());
+                }
+                Ok::<_, reqwest::Error>(addresses)
+            }
+            .await;
+
+            // Failed lookups are timed too, as they still hold up the request.
+            lookups.lock().unwrap().push(started.elapsed());
+
+            let addresses = answers?;
+            if addresses.is_empty() {
+                return Err(format!("no addresses found for {0} over DoH", name.as_str()).into());
+            }
+
+            let addresses: Addrs = Box::new(addresses.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addresses)
+        })
+    }
+}
+
+/// Resolves names with the system's resolver, as would otherwise be done, timing how long each lookup takes so that it
+/// isn't silently folded into the latency of the first requests.
+pub(crate) struct SystemResolver {
+    pub(crate) lookups: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let lookups = self.lookups.clone();
+
+        Box::pin(async move {
+            let started = Instant::now();
+            let addresses = tokio::net::lookup_host((name.as_str(), 0)).await;
+            lookups.lock().unwrap().push(started.elapsed());
+
+            let addresses: Addrs = Box::new(addresses?.collect_vec().into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+/// Resolves names by sending DNS queries over UDP to a DNS server.
+pub(crate) struct DnsServerResolver {
+    pub(crate) server: SocketAddr,
+    pub(crate) lookups: Arc<Mutex<Vec<Duration>>>,
+}
+
+/// The longest time to wait for a DNS server to answer a query.
+pub(crate) const DNS_SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Resolve for DnsServerResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (server, lookups) = (self.server, self.lookups.clone());
+
+        Box::pin(async move {
+            let started = Instant::now();
+            let (a, aaaa) = futures::join!(
+                query_dns_server(server, name.as_str(), DNS_TYPE_A),
+                query_dns_server(server, name.as_str(), DNS_TYPE_AAAA)
+            );
+            lookups.lock().unwrap().push(started.elapsed());
+
+            let addresses = [a?, aaaa?].concat();
+            if addresses.is_empty() {
+                return Err(
+                    format!("no addresses found for {0} via {1}", name.as_str(), server).into(),
+                );
+            }
+
+            let addresses: Addrs = Box::new(addresses.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addresses)
+        })
+    }
+}
+
+/// Sends a DNS query for records of the given type to a DNS server over UDP, returning the addresses in its answer.
+pub(crate) async fn query_dns_server(
+    server: SocketAddr,
+    name: &str,
+    record_type: u16,
+) -> Result<Vec<IpAddr>, Box<dyn std::error::Error + Send + Sync>> {
+    let query = async {
+        let socket = UdpSocket::bind(if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })
+        .await?;
+        socket.connect(server).await?;
+
+        let id = rand::thread_rng().gen::<u16>().to_be_bytes();
+        let mut query = get_dns_query(name, record_type);
+        query[..2].copy_from_slice(&id);
+        socket.send(&query).await?;
+
+        // Responses to earlier queries that timed out could still arrive, so they're skipped over.
+        let mut buffer = [0; 4096];
+        loop {
+            let length = socket.recv(&mut buffer).await?;
+            if buffer[..length].starts_with(&id) {
+                return Ok::<_, std::io::Error>(
+                    get_dns_answers(&buffer[..length]).unwrap_or_default(),
+                );
+            }
+        }
+    };
+
+    match tokio::time::timeout(DNS_SERVER_TIMEOUT, query).await {
+        Ok(answers) => Ok(answers?),
+        Err(_) => Err(format!("timed out looking up {0} via {1}", name, server).into()),
+    }
+}
+
+/// Builds a recursive DNS query, with an ID of 0 as recommended for DNS over HTTPS, for records of the given type.
+pub(crate) fn get_dns_query(name: &str, record_type: u16) -> Vec<u8> {
+    let mut query = vec![0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+
+    query
+}
+
+/// Gets the IPv4 and IPv6 addresses in the answers of a DNS response message, skipping any other records such as the
+/// CNAMEs that lead to them. None is returned if the message is malformed.
+pub(crate) fn get_dns_answers(message: &[u8]) -> Option<Vec<IpAddr>> {
+    let read_u16 = |at: usize| {
+        Some(u16::from_be_bytes([
+            *message.get(at)?,
+            *message.get(at + 1)?,
+        ]))
+    };
+
+    // Names are either a sequence of labels ending with an empty one, or end with a pointer to another name.
+    let skip_name = |mut at: usize| loop {
+        let length = *message.get(at)? as usize;
+        match length {
+            0 => return Some(at + 1),
+            l if l & 0xC0 == 0xC0 => return Some(at + 2),
+            l => at += l + 1,
+        }
+    };
+
+    let questions = read_u16(4)?;
+    let answers = read_u16(6)?;
+    let mut at = 12;
+
+    for _ in 0..questions {
+        at = skip_name(at)? + 4;
+    }
+
+    let mut addresses = vec![];
+    for _ in 0..answers {
+        at = skip_name(at)?;
+        let record_type = read_u16(at)?;
+        let length = read_u16(at + 8)? as usize;
+        let data = message.get(at + 10..at + 10 + length)?;
+
+        match (record_type, length) {
+            (DNS_TYPE_A, 4) => addresses.push(IpAddr::from(<[u8; 4]>::try_from(data).ok()?)),
+            (DNS_TYPE_AAAA, 16) => addresses.push(IpAddr::from(<[u8; 16]>::try_from(data).ok()?)),
+            _ => {}
+        }
+
+        at += 10 + length;
+    }
+
+    Some(addresses)
+}
+
+/// Picks which proxy the request with the given index, sent by the given virtual user (if any), goes through.
+pub(crate) fn get_proxy(args: &ParsedArgs, index: usize, user: Option<usize>) -> Option<usize> {
+    if args.proxies.is_empty() {
+        return None;
+    }
+
+    match (args.proxy_rotation, user) {
+        (ProxyRotation::User, Some(user)) => Some(user % args.proxies.len()),
+        _ => Some(index % args.proxies.len()),
+    }
+}
+
+/// Fetches the sitemap at the given URL and turns the URLs listed in it into targets, following any nested sitemap
+/// indexes (up to a limited depth). Gzipped sitemaps are decompressed, and sitemaps that can't be fetched are skipped.
+pub(crate) async fn get_sitemap_targets(
+    client: &reqwest::Client,
+    sitemap_url: &str,
+    method: &Method,
+    weighting: SitemapWeighting,
+) -> Vec<Target> {
+    let mut targets = vec![];
+    let mut visited = vec![];
+    let mut pending = vec![(sitemap_url.to_owned(), 0)];
+
+    while let Some((url, depth)) = pending.pop() {
+        if depth > MAX_SITEMAP_DEPTH || visited.contains(&url) {
+            continue;
+        }
+
+        let body = match fetch_sitemap(client, &url).await {
+            Some(b) => b,
+            None => continue,
+        };
+        visited.push(url);
+
+        if body.contains("<sitemapindex") {
+            for sitemap in get_xml_elements(&body, "sitemap") {
+                if let Some(loc) = get_xml_elements(sitemap, "loc").first() {
+                    pending.push((decode_xml_text(loc), depth + 1));
+                }
+            }
+            continue;
+        }
+
+        for entry in get_xml_elements(&body, "url") {
+            let loc = match get_xml_elements(entry, "loc").first() {
+                Some(l) => decode_xml_text(l),
+                None => continue,
+            };
+            let weight = match weighting {
+                SitemapWeighting::Uniform => 1,
+                SitemapWeighting::Priority => get_xml_elements(entry, "priority")
+                    .first()
+                    .and_then(|p| p.trim().parse::<f64>().ok())
+                    .map_or(5, |p| ((p.clamp(0.0, 1.0) * 10.0).round() as usize).max(1)),
+            };
+
+            targets.push(Target {
+                method: method.clone(),
+                url: loc,
+                weight,
+                proxy: None,
+                scenario: None,
+                cookies: None,
+                operation: None,
+            });
+        }
+    }
+
+    targets
+}
+
+/// Fetches the body of a sitemap, decompressing it if it's gzipped.
+pub(crate) async fn fetch_sitemap(client: &reqwest::Client, url: &str) -> Option<String> {
+    let response = client.get(url).send().await.ok()?.error_for_status().ok()?;
+    let bytes = response.bytes().await.ok()?;
+
+    // Gzipped sitemaps (e.g. sitemap.xml.gz) are served as files rather than with a content encoding.
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut body = String::new();
+        GzDecoder::new(&bytes[..]).read_to_string(&mut body).ok()?;
+        Some(body)
+    } else {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+/// Gets the contents of every element with the given name in a (trusted to be simple) XML document, such as a sitemap.
+pub(crate) fn get_xml_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let open = format!("<{0}", name);
+    let close = format!("</{0}>", name);
+    let mut elements = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        let after_name = &rest[start + open.len()..];
+
+        // Make sure this is the element itself and not just one whose name starts the same (e.g. `<urlset>`).
+        if !after_name.starts_with(['>', ' ', '\t', '\r', '\n']) {
+            rest = after_name;
+            continue;
+        }
+
+        let content_start = match after_name.find('>') {
+            Some(i) => i + 1,
+            None => break,
+        };
+        let content = &after_name[content_start..];
+        match content.find(&close) {
+            Some(end) => {
+                elements.push(&content[..end]);
+                rest = &content[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+
+    elements
+}
+
+/// Decodes the text content of an XML element, trimming it and unescaping the predefined entities and CDATA.
+pub(crate) fn decode_xml_text(text: &str) -> String {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+        .unwrap_or(text);
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Gets how long a worker pauses for before its next request, which is the delay with any jitter added at random.
+pub(crate) fn get_delay(args: &ParsedArgs) -> Option<Duration> {
+    let delay = args.delay.unwrap_or_default();
+
+    match args.jitter {
+        None => args.delay,
+        Some(Jitter::Range(min, max)) => Some(delay + rand::thread_rng().gen_range(min..=max)),
+        Some(Jitter::Fraction(fraction)) => {
+            Some(delay.mul_f64(rand::thread_rng().gen_range(1.0 - fraction..=1.0 + fraction)))
+        }
+    }
+}
+
+/// Picks the values for the placeholders of the next request, being the given row of the data file (or otherwise the
+/// next, if there is one) and the next place in the sequence, which is shared by its URL, headers and body.
+pub(crate) fn get_template_context<'a>(
+    args: &'a ParsedArgs,
+    state: &RunState,
+    row: Option<usize>,
+) -> TemplateContext<'a> {
+    // Each worker of a distributed run takes every nth place in the sequence, starting from its own.
+    let (worker, workers) = args.sequence_share;
+    let seq = state.sequence.fetch_add(1, Ordering::SeqCst) * workers + worker + 1;
+    let (Some(data), Some(row)) = (&args.data, row.or_else(|| get_data_row(args, state))) else {
+        return TemplateContext {
+            columns: &[],
+            row: &[],
+            seq,
+            variables: HashMap::new(),
+        };
+    };
+
+    TemplateContext {
+        columns: &data.columns,
+        row: &data.rows[row],
+        seq,
+        variables: HashMap::new(),
+    }
+}
+
+/// Picks the next row of the data file according to the chosen order, if there is a data file.
+pub(crate) fn get_data_row(args: &ParsedArgs, state: &RunState) -> Option<usize> {
+    let data = args.data.as_ref()?;
+    let index = match args.data_order {
+        TargetOrder::RoundRobin => state.data_rows_used.fetch_add(1, Ordering::SeqCst),
+        TargetOrder::Random => rand::thread_rng().gen_range(0..data.rows.len()),
+    };
+
+    Some(index % data.rows.len())
+}
+
+/// Evaluates the placeholders (e.g. `{{uuid}}`) in some text, such as a URL, for a single request. Anything that isn't a
+/// recognised placeholder is left as it is.
+pub(crate) fn render_template<'a>(template: &'a str, context: &TemplateContext) -> Cow<'a, str> {
+    if !template.contains("{{") {
+        return Cow::Borrowed(template);
+    }
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|e| start + e + 2) else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        match get_template_value(rest[start + 2..end - 2].trim(), context) {
+            Some(value) => rendered.push_str(&value),
+            None => rendered.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+
+    rendered.push_str(rest);
+    Cow::Owned(rendered)
+}
+
+/// Evaluates a single placeholder's expression, e.g. `rand_int 1 100` or the name of an extracted variable or data file
+/// column, returning None if it isn't recognised.
+pub(crate) fn get_template_value(expression: &str, context: &TemplateContext) -> Option<String> {
+    if let Some(value) = context.variables.get(expression) {
+        return Some(value.clone());
+    }
+    if let Some(column) = context.columns.iter().position(|c| c == expression) {
+        return Some(context.row[column].clone());
+    }
+
+    let parts = expression.split_whitespace().collect_vec();
+
+    match parts[..] {
+        ["uuid"] => Some(get_uuid()),
+        ["seq"] => Some(context.seq.to_string()),
+        ["timestamp"] => Some(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()?
+                .as_secs()
+                .to_string(),
+        ),
+        ["rand_int", min, max] => {
+            let (min, max) = (min.parse::<i64>().ok()?, max.parse::<i64>().ok()?);
+            (min <= max).then(|| rand::thread_rng().gen_range(min..=max).to_string())
+        }
+        [generator] if generator.starts_with("fake.") => get_fake_value(&generator[5..]),
+        _ => None,
+    }
+}
+
+/// The first names that fake names and email addresses are made up from.
+pub(crate) const FAKE_FIRST_NAMES: [&str; 16] = [
+    "Alice", "Bob", "Charlie", "Dana", "Eve", "Frank", "Grace", "Heidi", "Ivan", "Judy", "Mallory",
+    "Niaj", "Olivia", "Peggy", "Rupert", "Sybil",
+];
+
+/// The last names that fake names and email addresses are made up from.
+pub(crate) const FAKE_LAST_NAMES: [&str; 16] = [
+    "Smith", "Jones", "Taylor", "Brown", "Williams", "Wilson", "Johnson", "Davies", "Patel",
+    "Robinson", "Wright", "Thompson", "Evans", "Walker", "White", "Roberts",
+];
+
+/// The words that fake words are picked from.
+pub(crate) const FAKE_WORDS: [&str; 12] = [
+    "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliet",
+    "kilo", "lima",
+];
+
+/// Generates a plausible random value of the given kind, e.g. `email`, returning None if there is no such generator.
+/// Email addresses (and usernames) include a random number so that they are unique in all but the longest runs.
+pub(crate) fn get_fake_value(kind: &str) -> Option<String> {
+    let mut rng = rand::thread_rng();
+    let first_name = FAKE_FIRST_NAMES[rng.gen_range(0..FAKE_FIRST_NAMES.len())];
+    let last_name = FAKE_LAST_NAMES[rng.gen_range(0..FAKE_LAST_NAMES.len())];
+
+    match kind {
+        "name" => Some(format!("{0} {1}", first_name, last_name)),
+        "first_name" => Some(first_name.to_string()),
+        "last_name" => Some(last_name.to_string()),
+        "username" => Some(format!(
+            "{0}{1}",
+            first_name.to_ascii_lowercase(),
+            rng.gen_range(0..1_000_000)
+        )),
+        "email" => Some(format!(
+            "{0}.{1}{2}@example.com",
+            first_name.to_ascii_lowercase(),
+            last_name.to_ascii_lowercase(),
+            rng.gen_range(0..1_000_000)
+        )),
+        "phone" => Some(format!("+1555{0:07}", rng.gen_range(0..10_000_000))),
+        "ipv4" => Some(IpAddr::from(rng.gen::<[u8; 4]>()).to_string()),
+        "ipv6" => Some(IpAddr::from(rng.gen::<[u8; 16]>()).to_string()),
+        "word" => Some(FAKE_WORDS[rng.gen_range(0..FAKE_WORDS.len())].to_string()),
+        _ => None,
+    }
+}
+
+/// Generates a random (version 4) UUID.
+pub(crate) fn get_uuid() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes.iter().map(|b| format!("{0:02x}", b)).join("");
+    format!(
+        "{0}-{1}-{2}-{3}-{4}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Waits until the next request should be sent according to the load pattern's current rate (scaled by any live
+/// adjustments), moving the scheduled send time on from the previous one so that time spent dispatching requests
+/// doesn't drift the rate. Whilst the rate is zero it is re-checked every 100 milliseconds until it picks back up, and
+/// rates of less than one request a second are waited on a second at a time so that changes to them are picked up
+/// before the next request is due, with each second counting for as much of the wait as the rate it had.
+pub(crate) async fn wait_for_next_request(
+    state: &RunState,
+    args: &ParsedArgs,
+    next_request_at: &mut Instant,
+) {
+    // How much of the gap between requests is left to wait, where a whole gap is one over whatever the rate is.
+    let mut remaining = 1.0;
+
+    loop {
+        let elapsed = next_request_at.duration_since(state.started);
+
+        // The rate ramps down to zero at the very end of a run, so it can't be waited on to pick back up.
+        if args.duration.is_some_and(|d| elapsed >= d) {
+            return;
+        }
+
+        let rate = state
+            .pattern
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0.0, |p| p.rate_at(elapsed))
+            * *state.rate_multiplier.lock().unwrap()
+            * get_ramp_factor(args, elapsed);
+        if rate > 0.0 {
+            let wait = remaining / rate;
+            let due = wait <= 1.0;
+            *next_request_at += Duration::from_secs_f64(wait.min(1.0));
+            remaining -= rate;
+
+            // Slow rates mustn't keep the run going past its end.
+            let end = args.duration.map(|d| state.started + d);
+            let wake_at = end.map_or(*next_request_at, |e| e.min(*next_request_at));
+            tokio::time::sleep_until(wake_at.into()).await;
+            if due {
+                return;
+            }
+            continue;
+        }
+
+        *next_request_at += Duration::from_millis(100);
+        tokio::time::sleep_until((*next_request_at).into()).await;
+    }
+}
+
+/// Reads live controls from stdin on a background thread for the rest of the run. A line of `mark LABEL` records a
+/// marker event with the given label. Otherwise each `+` in a line raises the load and each `-` lowers it, with every
+/// change recorded as an event: if a pattern is being followed its rate is changed by 10% of the pattern, otherwise the
+/// concurrency or number of virtual users is changed by 10% of what it started at (or at least one), down to one.
+pub(crate) fn read_load_controls(
+    client: Arc<reqwest::Client>,
+    args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+) {
+    // The controls are read on a thread of their own, but the virtual users they add run on the run's runtime.
+    let runtime = tokio::runtime::Handle::current();
+    let step = (args.virtual_users.or(args.concurrency).unwrap_or(0) / 10).max(1);
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => return,
+            };
+
+            if let Some(label) = line.trim().strip_prefix("mark ") {
+                state.record_event(format!("marker: {0}", label.trim()));
+                continue;
+            }
+
+            let steps = line.chars().filter(|c| *c == '+').count() as isize
+                - line.chars().filter(|c| *c == '-').count() as isize;
+            if steps == 0 {
+                continue;
+            }
+
+            if state.pattern.lock().unwrap().is_some() {
+                let multiplier = {
+                    let mut multiplier = state.rate_multiplier.lock().unwrap();
+                    *multiplier = (*multiplier + steps as f64 * 0.1).max(0.0);
+                    *multiplier
+                };
+
+                state.record_event(format!(
+                    "rate adjusted to {0:.0}% of the pattern",
+                    multiplier * 100.0
+                ));
+            } else if let Some(concurrency) = state.concurrency.lock().unwrap().clone() {
+                let (previous, limit) = adjust_concurrency_limit(&state, steps * step as isize);
+                if limit > previous {
+                    concurrency.add_permits(limit - previous);
+                } else if limit < previous {
+                    // Permits that are in use are taken away as the requests holding them complete.
+                    let taken = (previous - limit) as u32;
+                    runtime.spawn(async move {
+                        if let Ok(permits) = concurrency.acquire_many_owned(taken).await {
+                            permits.forget();
+                        }
+                    });
+                }
+
+                state.record_event(format!("concurrency adjusted to {0}", limit));
+            } else if args.virtual_users.is_some() {
+                let (previous, limit) = adjust_concurrency_limit(&state, steps * step as isize);
+                let mut added_virtual_users = state.added_virtual_users.lock().unwrap();
+                let Some(added_virtual_users) = added_virtual_users.as_mut() else {
+                    return;
+                };
+                for user in previous..limit {
+                    added_virtual_users.push(runtime.spawn(run_virtual_user(
+                        client.clone(),
+                        args.clone(),
+                        state.clone(),
+                        user,
+                    )));
+                }
+                if limit < previous {
+                    state
+                        .virtual_users_to_stop
+                        .fetch_add(previous - limit, Ordering::SeqCst);
+                }
+
+                state.record_event(format!("virtual users adjusted to {0}", limit));
+            }
+        }
+    });
+}
+
+/// Adjusts how many requests (or virtual users) the run allows at once by the given amount, down to one, giving what it
+/// was before and is now.
+pub(crate) fn adjust_concurrency_limit(state: &RunState, adjustment: isize) -> (usize, usize) {
+    let previous = state
+        .concurrency_limit
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |l| {
+            Some(l.saturating_add_signed(adjustment).max(1))
+        })
+        .unwrap();
+    (previous, previous.saturating_add_signed(adjustment).max(1))
+}
+
+/// Polls a load pattern's points file every second for the rest of the run, swapping the pattern being followed for the
+/// file's new contents whenever it is modified. Changes that fail to parse are ignored and the previous pattern is kept.
+/// Every reload attempt is recorded as an event.
+pub(crate) async fn watch_pattern_file(path: String, state: Arc<RunState>) {
+    let modified_at = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified_at = modified_at(&path);
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let current_modified_at = modified_at(&path);
+        if current_modified_at == last_modified_at {
+            continue;
+        }
+        last_modified_at = current_modified_at;
+
+        match parse_pattern(&format!("curve:@{0}", path)) {
+            Some(pattern) => {
+                let description = format!("pattern reloaded from {0}: {1}", path, pattern);
+                *state.pattern.lock().unwrap() = Some(pattern);
+                state.record_event(description);
+            }
+            None => state.record_event(format!(
+                "pattern reload from {0} failed, keeping the previous pattern",
+                path
+            )),
+        }
+    }
+}
+
+/// Polls a scenario file every second for the rest of the run, swapping the scenario that iterations start with for the
+/// file's new contents whenever it is modified, along with the rate if the file gives one and the run is at a constant
+/// rate. Its steps' requests and think times can change, but not which steps there are, as the results are broken down
+/// by them; changes that do, or that fail to parse, are ignored and the previous scenario is kept. Every reload attempt
+/// is recorded as an event.
+pub(crate) async fn watch_scenario_file(path: String, state: Arc<RunState>) {
+    let modified_at = |path: &str| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified_at = modified_at(&path);
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let current_modified_at = modified_at(&path);
+        if current_modified_at == last_modified_at {
+            continue;
+        }
+        last_modified_at = current_modified_at;
+
+        let Some(scenario) = parse_scenario_file(&path) else {
+            state.record_event(format!(
+                "scenario reload from {0} failed, keeping the previous scenario",
+                path
+            ));
+            continue;
+        };
+        if scenario
+            .rate
+            .is_some_and(|rate| rate <= 0.0 || !rate.is_finite())
+        {
+            state.record_event(format!(
+                "scenario reload from {0} ignored as its rate is invalid, keeping the previous scenario",
+                path
+            ));
+            continue;
+        }
+
+        let mut current = state.scenario.lock().unwrap();
+        let same_steps = current.as_ref().is_some_and(|c| {
+            c.steps.len() == scenario.steps.len()
+                && c.steps
+                    .iter()
+                    .zip(&scenario.steps)
+                    .all(|(a, b)| a.name == b.name)
+        });
+        if !same_steps {
+            state.record_event(format!(
+                "scenario reload from {0} ignored as its steps changed, keeping the previous scenario",
+                path
+            ));
+            continue;
+        }
+
+        let mut description = format!("scenario reloaded from {0}", path);
+        if let Some(rate) = scenario.rate {
+            let mut pattern = state.pattern.lock().unwrap();
+            if let Some(LoadPattern::Constant(current_rate)) = *pattern {
+                if current_rate != rate {
+                    *pattern = Some(LoadPattern::Constant(rate));
+                    description.push_str(&format!(", rate now {0}/s", rate));
+                }
+            }
+        }
+        *current = Some(Arc::new(scenario));
+        drop(current);
+        state.record_event(description);
+    }
+}
+
+/// Records a numbered marker event every time the process receives SIGUSR2, for the rest of the run.
+#[cfg(unix)]
+pub(crate) async fn record_signal_markers(state: Arc<RunState>) {
+    let mut signals = match signal(SignalKind::user_defined2()) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    let mut count = 0;
+    while signals.recv().await.is_some() {
+        count += 1;
+        state.record_event(format!("marker: SIGUSR2 #{0}", count));
+    }
+}
+
+/// Stops the run early the first time the process is interrupted (by Ctrl+C), so that what has completed so far can be
+/// reported on, and exits straight away the second time.
+pub(crate) async fn stop_on_interrupt(state: Arc<RunState>) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    println!(
+        "\n🛑 Stopping early, waiting up to {0}s for the {1} requests in flight (Ctrl+C again to exit straight away)",
+        STOP_GRACE_PERIOD.as_secs(),
+        state.in_flight.load(Ordering::SeqCst)
+    );
+    state.stop(
+        StopReason::Interrupted,
+        String::from("Stopped early by Ctrl+C"),
+    );
+
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
+/// Samples the number of requests in flight at a fixed interval for the rest of the run.
+pub(crate) async fn sample_in_flight_requests(state: Arc<RunState>) {
+    let mut interval = tokio::time::interval(IN_FLIGHT_SAMPLE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let in_flight = state.in_flight.load(Ordering::SeqCst);
+        let peak = state.in_flight_peak.swap(in_flight, Ordering::SeqCst);
+        state
+            .in_flight_samples
+            .lock()
+            .unwrap()
+            .push(InFlightSample {
+                at: state.started.elapsed(),
+                in_flight,
+                peak: peak.max(in_flight),
+            });
+    }
+}
+
+impl<'a> InFlightRequest<'a> {
+    /// Marks a request as in flight until the returned value is dropped.
+    pub(crate) fn start(state: &'a RunState) -> Self {
+        let in_flight = state.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        state.in_flight_peak.fetch_max(in_flight, Ordering::SeqCst);
+        InFlightRequest { state }
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.state.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RunState {
+    /// Records an event as having happened now, printing it if the run's progress is being printed so that it's visible
+    /// whilst the run is in progress.
+    pub(crate) fn record_event(&self, description: String) {
+        let at = self.started.elapsed();
+        if self.printing.load(Ordering::SeqCst) {
+            println!("\t📍 {0:.1}s: {1}", at.as_secs_f64(), description);
+        }
+        self.events
+            .lock()
+            .unwrap()
+            .push(RunEvent { at, description });
+    }
+
+    /// Samples the start of the body of a failed request's response, counting it against the same start if it has
+    /// been seen before. Empty bodies aren't sampled, as they say no more than the status code does.
+    pub(crate) fn sample_failure_body(&self, status: u16, body: &str) {
+        let body = body
+            .trim()
+            .chars()
+            .take(FAILURE_BODY_SAMPLE_LENGTH)
+            .collect::<String>();
+        if body.is_empty() {
+            return;
+        }
+        let mut samples = self.failure_bodies.lock().unwrap();
+        if let Some(sample) = samples
+            .iter_mut()
+            .find(|s| s.status == status && s.body == body)
+        {
+            sample.count += 1;
+        } else if samples.len() < MAX_FAILURE_BODY_SAMPLES {
+            samples.push(FailureBody {
+                status,
+                body,
+                count: 1,
+            });
+        }
+    }
+
+    /// Stops the run early (unless it has already been), recording why.
+    pub(crate) fn stop(&self, reason: StopReason, description: String) {
+        let stopping = self.stopped.send_if_modified(|stopped| {
+            let stopping = stopped.is_none();
+            stopped.get_or_insert((Instant::now(), reason));
+            stopping
+        });
+        if stopping {
+            self.record_event(description);
+        }
+    }
+
+    /// Checks whether the run has been stopped early.
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.borrow().is_some()
+    }
+
+    /// Gets why the run was stopped early, if it was.
+    pub(crate) fn stop_reason(&self) -> Option<StopReason> {
+        self.stopped.borrow().map(|(_, reason)| reason)
+    }
+
+    /// Waits until the run has been stopped early.
+    pub(crate) async fn wait_for_stop(&self) {
+        let _ = self.stopped.subscribe().wait_for(Option::is_some).await;
+    }
+
+    /// Waits until the run has been stopped early and the requests that were in flight have had their grace period
+    /// to complete.
+    pub(crate) async fn wait_for_stop_grace_period(&self) {
+        let mut stopped = self.stopped.subscribe();
+        let Ok((stopped_at, _)) = stopped.wait_for(Option::is_some).await.map(|s| s.unwrap())
+        else {
+            return;
+        };
+        tokio::time::sleep_until((stopped_at + STOP_GRACE_PERIOD).into()).await;
+    }
+}
+
+/// Picks the target for the request with the given index. Targets from a URL file are picked according to the chosen
+/// order, otherwise requests alternate between the URL and the URL being compared with (if there is one) so that they
+/// see identical load.
+pub(crate) fn get_target(args: &ParsedArgs, index: usize) -> Target {
+    if !args.targets.is_empty() {
+        let total_weight: usize = args.targets.iter().map(|t| t.weight).sum();
+        let mut position = match args.target_order {
+            TargetOrder::RoundRobin => index % total_weight,
+            TargetOrder::Random => rand::thread_rng().gen_range(0..total_weight),
+        };
+
+        for target in &args.targets {
+            if position < target.weight {
+                return target.clone();
+            }
+            position -= target.weight;
+        }
+    }
+
+    let url = match &args.compare_url {
+        Some(compare_url) if index % 2 == 1 => compare_url.clone(),
+        _ => args.url.clone(),
+    };
+
+    Target {
+        method: args.method.clone(),
+        url,
+        weight: 1,
+        proxy: None,
+        scenario: None,
+        cookies: None,
+        operation: None,
+    }
+}
+
+/// Represents the cookies of one session, being an iteration along with each scenario step, page and retry in it, which
+/// starts with the cookies given on the command line and keeps those that its responses set.
+#[derive(Default)]
+pub(crate) struct CookieJar {
+    pub(crate) cookies: Vec<Cookie>,
+}
+
+/// Represents a cookie in a jar, which is sent to its domain (and the domain's subdomains) for the paths under its path.
+pub(crate) struct Cookie {
+    pub(crate) name: String,
+    pub(crate) value: String,
+    /// The domain that the cookie is sent to, or none if it was given on the command line and is sent to every host.
+    pub(crate) domain: Option<String>,
+    pub(crate) path: String,
+}
+
+impl CookieJar {
+    /// Starts a session's jar with the cookies given on the command line.
+    pub(crate) fn new(args: &ParsedArgs) -> CookieJar {
+        let cookies = args
+            .seed_cookies
+            .iter()
+            .map(|(name, value)| Cookie {
+                name: name.clone(),
+                value: value.clone(),
+                domain: None,
+                path: String::from("/"),
+            })
+            .collect();
+
+        CookieJar { cookies }
+    }
+
+    /// Gets the value of the Cookie header to send with a request to the URL, if any of the cookies are for it.
+    pub(crate) fn get_header(&self, url: &Url) -> Option<String> {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let header = self
+            .cookies
+            .iter()
+            .filter(|c| c.is_for(&host, url.path()))
+            .map(|c| format!("{0}={1}", c.name, c.value))
+            .join("; ");
+
+        (!header.is_empty()).then_some(header)
+    }
+
+    /// Keeps the cookies set by a response from the URL, replacing any that they were already set to (or were given on
+    /// the command line as) and removing those that are set to have expired, as servers do to log users out.
+    pub(crate) fn store(&mut self, headers: &HeaderMap, url: &Url) {
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        let default_path = match url.path().rfind('/') {
+            Some(0) | None => "/",
+            Some(end) => &url.path()[..end],
+        };
+
+        for set_cookie in headers.get_all(SET_COOKIE).iter() {
+            let Ok(set_cookie) = set_cookie.to_str() else {
+                continue;
+            };
+            let mut attributes = set_cookie.split(';');
+            let Some((name, value)) = attributes.next().and_then(|c| c.split_once('=')) else {
+                continue;
+            };
+
+            let mut cookie = Cookie {
+                name: name.trim().to_owned(),
+                value: value.trim().trim_matches('"').to_owned(),
+                domain: Some(host.clone()),
+                path: default_path.to_owned(),
+            };
+            let mut expired = false;
+            for attribute in attributes {
+                let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+                let value = value.trim();
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "domain" if !value.is_empty() => {
+                        cookie.domain = Some(value.trim_start_matches('.').to_ascii_lowercase())
+                    }
+                    "path" if value.starts_with('/') => cookie.path = value.to_owned(),
+                    "max-age" => expired = value.parse::<i64>().is_ok_and(|age| age <= 0),
+                    "expires" => {
+                        expired = httpdate::parse_http_date(value)
+                            .is_ok_and(|expires| expires <= SystemTime::now())
+                    }
+                    _ => {}
+                }
+            }
+
+            self.cookies.retain(|c| {
+                c.name != cookie.name
+                    || (c.domain.is_some() && (c.domain != cookie.domain || c.path != cookie.path))
+            });
+            if !expired && !cookie.name.is_empty() {
+                self.cookies.push(cookie);
+            }
+        }
+    }
+}
+
+impl Cookie {
+    /// Checks whether the cookie is sent with requests to the host and path.
+    pub(crate) fn is_for(&self, host: &str, path: &str) -> bool {
+        let is_for_host = self.domain.as_ref().is_none_or(|domain| {
+            host == domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|subdomain| subdomain.ends_with('.'))
+        });
+        let is_for_path = path == self.path
+            || path
+                .strip_prefix(self.path.as_str())
+                .is_some_and(|rest| self.path.ends_with('/') || rest.starts_with('/'));
+
+        is_for_host && is_for_path
+    }
+}
+
+/// Starts the jar for a new session, if cookies are being sent.
+pub(crate) fn get_cookie_jar(args: &ParsedArgs) -> Option<Arc<Mutex<CookieJar>>> {
+    (args.cookies || !args.seed_cookies.is_empty())
+        .then(|| Arc::new(Mutex::new(CookieJar::new(args))))
+}
+
+/// Dispatches the requests for a run that's based on a total count of requests, pacing them according to any load
+/// pattern or replay timing, and returns the handles of the tasks performing them. With a concurrency limit, each
+/// request waits to be dispatched until there's room for it to be in flight.
+pub(crate) async fn dispatch_requests(
+    client: &Arc<reqwest::Client>,
+    args: &Arc<ParsedArgs>,
+    state: &Arc<RunState>,
+) -> Vec<JoinHandle<Vec<ResponseStatistics>>> {
+    let mut next_request_at = state.started;
+    let mut requests = vec![];
+    let concurrency = args.concurrency.map(|c| {
+        let concurrency = Arc::new(Semaphore::new(if args.ramp_up.is_some() { 0 } else { c }));
+        tokio::spawn(ramp_concurrency(
+            concurrency.clone(),
+            args.clone(),
+            state.clone(),
+        ));
+        *state.concurrency.lock().unwrap() = Some(concurrency.clone());
+        concurrency
+    });
+    for i in 0..args.count {
+        // Requests are due as soon as they're dispatched unless something is pacing them.
+        let dispatched_at = Instant::now();
+        let mut scheduled_at = dispatched_at;
+
+        // Waiting for the request to be due is cut short if the run is stopped early in the meantime.
+        let due = async {
+            if args.pattern.is_some() && i > 0 {
+                wait_for_next_request(state, args, &mut next_request_at).await;
+                scheduled_at = next_request_at;
+            }
+
+            // Replays preserving the original timing send each request its (sped up) offset after the start, looping
+            // back around when replaying more requests than there are in the log.
+            if let Some(Replay {
+                offsets,
+                speed: Some(speed),
+                ..
+            }) = &args.replay
+            {
+                let index = i as usize % offsets.len();
+                let loops = (i as usize / offsets.len()) as u32;
+                let offset = offsets[index] + offsets[offsets.len() - 1] * loops;
+                scheduled_at = state.started + offset.div_f64(*speed);
+                tokio::time::sleep_until(scheduled_at.into()).await;
+            }
+
+            match &concurrency {
+                Some(concurrency) => Some(concurrency.clone().acquire_owned().await.unwrap()),
+                None => None,
+            }
+        };
+        let permit = tokio::select! {
+            permit = due => permit,
+            _ = state.wait_for_stop() => break,
+        };
+        if state.is_stopped() {
+            break;
+        }
+
+        // Without anything pacing them, requests are only due once there's room for them to be in flight.
+        if scheduled_at == dispatched_at {
+            scheduled_at = Instant::now();
+        }
+
+        if args.duration.is_some_and(|d| state.started.elapsed() >= d) {
+            break;
+        }
+
+        let target = Target {
+            proxy: get_proxy(args, i as usize, None),
+            ..get_target(args, i as usize)
+        };
+        let ramp = get_ramp(args, scheduled_at.saturating_duration_since(state.started));
+        let iteration = perform_iteration(
+            client.clone(),
+            args.clone(),
+            state.clone(),
+            target,
+            scheduled_at,
+        );
+        let delay = get_delay(args);
+        let iteration_state = state.clone();
+        requests.push(tokio::spawn(async move {
+            // Iterations still in flight once a run that was stopped early has had its grace period are abandoned.
+            let results = tokio::select! {
+                results = iteration => results,
+                _ = iteration_state.wait_for_stop_grace_period() => vec![],
+            };
+
+            // The room for the next request is only made once the delay is over, without holding up the results.
+            match (delay, permit) {
+                (Some(delay), Some(permit)) => {
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        drop(permit);
+                    });
+                }
+                (_, permit) => drop(permit),
+            }
+
+            results
+                .into_iter()
+                .map(|r| ResponseStatistics { ramp, ..r })
+                .collect()
+        }));
+    }
+
+    requests
+}
+
+/// Runs a single virtual user, which performs its iterations one after the other. With pacing, each iteration starts a
+/// fixed interval after the previous one started (or as soon as it finishes if it overran the interval, which is
+/// recorded). Targets are picked as if the virtual users were taking turns, so that round-robin ordering is kept across
+/// all of them.
+pub(crate) async fn run_virtual_user(
+    client: Arc<reqwest::Client>,
+    args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    user: usize,
+) -> Vec<ResponseStatistics> {
+    let virtual_users = args.virtual_users.unwrap_or(1);
+    let ramp_down_start = get_ramp_down_start(&args).map(|s| state.started + s);
+    let mut results = vec![];
+
+    // Whilst ramping up, the virtual users start one after another so that the first starts straight away.
+    let started = state.started
+        + args.ramp_up.map_or(Duration::ZERO, |r| {
+            r.mul_f64((user as f64 / virtual_users as f64).min(1.0))
+        });
+    tokio::time::sleep_until(started.into()).await;
+
+    // Whilst ramping down, the virtual users stop one after another so that the last stops at the end of the run.
+    let stop_at = ramp_down_start
+        .zip(args.ramp_down)
+        .map(|(start, ramp_down)| {
+            start + ramp_down.mul_f64(((user + 1) as f64 / virtual_users as f64).min(1.0))
+        })
+        .or(args.duration.map(|d| state.started + d));
+
+    for iteration in 0..args.iterations {
+        let scheduled_at = match args.pacing {
+            Some(pacing) => started + pacing * iteration as u32,
+            None => Instant::now(),
+        };
+
+        if stop_at.is_some_and(|s| scheduled_at >= s) || state.is_stopped() {
+            break;
+        }
+
+        // Live controls lower the number of virtual users by having the next ones to start an iteration stop instead.
+        if state
+            .virtual_users_to_stop
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| s.checked_sub(1))
+            .is_ok()
+        {
+            break;
+        }
+
+        if args.pacing.is_some() {
+            let now = Instant::now();
+
+            if now > scheduled_at && iteration > 0 {
+                state
+                    .pacing_overruns
+                    .lock()
+                    .unwrap()
+                    .push(now - scheduled_at);
+            } else {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(scheduled_at.into()) => {}
+                    _ = state.wait_for_stop() => break,
+                }
+            }
+        }
+
+        let index = iteration * virtual_users + user;
+        let target = Target {
+            proxy: get_proxy(&args, index, Some(user)),
+            ..get_target(&args, index)
+        };
+        let ramp = get_ramp(&args, scheduled_at.saturating_duration_since(state.started));
+        let performed = perform_iteration(
+            client.clone(),
+            args.clone(),
+            state.clone(),
+            target,
+            scheduled_at,
+        );
+        // An iteration still in flight once a run that was stopped early has had its grace period is abandoned.
+        let performed = tokio::select! {
+            performed = performed => performed,
+            _ = state.wait_for_stop_grace_period() => break,
+        };
+        results.extend(
+            performed
+                .into_iter()
+                .map(|r| ResponseStatistics { ramp, ..r }),
+        );
+
+        if let Some(delay) = get_delay(&args).filter(|_| iteration + 1 < args.iterations) {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = state.wait_for_stop() => break,
+            }
+        }
+    }
+
+    results
+}
+
+/// Gets how long after the start of the run ramping down begins, if it does at all.
+pub(crate) fn get_ramp_down_start(args: &ParsedArgs) -> Option<Duration> {
+    Some(get_run_length(args)? - args.ramp_down?)
+}
+
+/// Gets how long the run will last, if that's known up front, which it is for runs with a duration or a fixed number of
+/// paced iterations.
+pub(crate) fn get_run_length(args: &ParsedArgs) -> Option<Duration> {
+    let paced = args
+        .pacing
+        .and_then(|p| p.checked_mul(u32::try_from(args.iterations).ok()?));
+
+    match (args.duration, paced) {
+        (Some(duration), Some(paced)) => Some(duration.min(paced)),
+        (duration, paced) => duration.or(paced),
+    }
+}
+
+/// Gets which ramp, if any, the run is in at the given point.
+pub(crate) fn get_ramp(args: &ParsedArgs, elapsed: Duration) -> Option<Ramp> {
+    if args.ramp_up.is_some_and(|r| elapsed < r) {
+        Some(Ramp::Up)
+    } else if get_ramp_down_start(args).is_some_and(|s| elapsed >= s) {
+        Some(Ramp::Down)
+    } else {
+        None
+    }
+}
+
+/// Gets how much of the target load to apply at the given point in the run, growing linearly from nothing to all of it
+/// whilst ramping up and shrinking back to nothing whilst ramping down.
+pub(crate) fn get_ramp_factor(args: &ParsedArgs, elapsed: Duration) -> f64 {
+    match get_ramp(args, elapsed) {
+        Some(Ramp::Up) => elapsed.as_secs_f64() / args.ramp_up.unwrap().as_secs_f64(),
+        Some(Ramp::Down) => {
+            let remaining = get_run_length(args).unwrap().saturating_sub(elapsed);
+            remaining.as_secs_f64() / args.ramp_down.unwrap().as_secs_f64()
+        }
+        None => 1.0,
+    }
+}
+
+/// Ramps the number of requests allowed in flight at once, adding permits one at a time whilst ramping up and taking
+/// them away one at a time whilst ramping down. The last is left for the rest of the run so that it can finish.
+pub(crate) async fn ramp_concurrency(
+    concurrency: Arc<Semaphore>,
+    args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+) {
+    let permits = args.concurrency.unwrap_or(1);
+
+    if let Some(ramp_up) = args.ramp_up {
+        for permit in 0..permits {
+            let at = state.started + ramp_up.mul_f64(permit as f64 / permits as f64);
+            tokio::time::sleep_until(at.into()).await;
+            concurrency.add_permits(1);
+        }
+    }
+
+    if let (Some(start), Some(ramp_down)) = (get_ramp_down_start(&args), args.ramp_down) {
+        for permit in 1..permits {
+            let at = state.started + start + ramp_down.mul_f64(permit as f64 / permits as f64);
+            tokio::time::sleep_until(at.into()).await;
+            concurrency.clone().acquire_owned().await.unwrap().forget();
+        }
+    }
+}
+
+/// Records events as the run finishes ramping up and starts ramping down.
+pub(crate) async fn record_ramp_events(state: Arc<RunState>, args: Arc<ParsedArgs>) {
+    if let Some(ramp_up) = args.ramp_up {
+        tokio::time::sleep_until((state.started + ramp_up).into()).await;
+        state.record_event(String::from("Ramped up"));
+    }
+
+    if let Some(ramp_down_start) = get_ramp_down_start(&args) {
+        tokio::time::sleep_until((state.started + ramp_down_start).into()).await;
+        state.record_event(String::from("Ramping down"));
+    }
+}
+
+/// Performs a single iteration against the target, which is either one request or a walk through its pages. The time
+/// it was scheduled to start at is used to record how late its first request was sent.
+pub(crate) async fn perform_iteration(
+    client: Arc<reqwest::Client>,
+    args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+    scheduled_at: Instant,
+) -> Vec<ResponseStatistics> {
+    let scheduling_delay = scheduled_at.elapsed();
+    let target = Target {
+        cookies: get_cookie_jar(&args),
+        ..target
+    };
+
+    let mut results = if args.scenario.is_some() {
+        perform_scenario(client, args.clone(), state.clone(), target).await
+    } else if args.max_pages > 1 {
+        perform_paginated_requests(client, args.clone(), state.clone(), target).await
+    } else {
+        vec![perform_request_with_retries(client, args.clone(), state.clone(), target).await]
+    };
+
+    if let Some(first) = results.first_mut() {
+        first.scheduling_delay = Some(scheduling_delay);
+    }
+
+    if let Some(request_log) = &state.request_log {
+        if let Err(e) = write_request_log_lines(&mut *request_log.lock().unwrap(), &results, &args)
+        {
+            eprintln!("Failed to write to the request log: {0}", e);
+        }
+    }
+
+    if let Some(events) = &*state.request_events.lock().unwrap() {
+        for result in &results {
+            let _ = events.send(RequestEvent::from_result(result));
+        }
+    }
+
+    let completed = state.completed.fetch_add(results.len(), Ordering::SeqCst) + results.len();
+    let failures = results.iter().filter(|r| !r.is_success).count();
+    let failed = state.failed.fetch_add(failures, Ordering::SeqCst) + failures;
+    if failures > 0 {
+        check_error_limit(&args, &state, completed, failed);
+    }
+    state
+        .recently_completed
+        .lock()
+        .unwrap()
+        .extend(results.iter().map(|r| r.response_time));
+    state.metrics.lock().unwrap().record(&results);
+
+    results
+}
+
+/// Aborts the run if more of its requests have failed than its error limit allows.
+pub(crate) fn check_error_limit(
+    args: &ParsedArgs,
+    state: &RunState,
+    completed: usize,
+    failed: usize,
+) {
+    let exceeded = match args.abort_on_errors {
+        Some(ErrorLimit::Count(count)) => failed >= count,
+        Some(ErrorLimit::Percentage(percentage)) => {
+            completed >= MIN_ABORT_REQUESTS && failed as f64 / completed as f64 * 100.0 > percentage
+        }
+        None => false,
+    };
+
+    if exceeded {
+        state.stop(
+            StopReason::Errors,
+            format!(
+                "Aborted after {0} of {1} requests failed",
+                failed, completed
+            ),
+        );
+    }
+}
+
+/// Performs each of the scenario's steps in order as one iteration, through the target's proxy, stopping at the first
+/// that fails as the steps after it are likely to rely on it.
+pub(crate) async fn perform_scenario(
+    client: Arc<reqwest::Client>,
+    args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+) -> Vec<ResponseStatistics> {
+    let row = get_data_row(&args, &state);
+    let variables = Arc::new(Mutex::new(HashMap::new()));
+    let scenario = state.scenario.lock().unwrap().clone().unwrap();
+    let mut results = vec![];
+
+    for (step, definition) in scenario.steps.iter().enumerate() {
+        let target = Target {
+            method: definition.method.clone(),
+            url: definition.url.clone(),
+            scenario: Some(ScenarioPosition {
+                step,
+                row,
+                variables: variables.clone(),
+                scenario: scenario.clone(),
+            }),
+            ..target.clone()
+        };
+        let result =
+            perform_request_with_retries(client.clone(), args.clone(), state.clone(), target).await;
+
+        let failed = !result.is_success;
+        results.push(result);
+        if failed {
+            break;
+        }
+
+        // The pause after the last step is left to the pacing between iterations.
+        if let Some(think_time) = definition
+            .think_time
+            .filter(|_| step + 1 < scenario.steps.len())
+        {
+            tokio::time::sleep(think_time).await;
+        }
+    }
+
+    results
+}
+
+impl Metrics {
+    /// Adds completed requests to the totals.
+    pub(crate) fn record(&mut self, results: &[ResponseStatistics]) {
+        for result in results {
+            let status = result
+                .status_code
+                .map_or_else(|| String::from("none"), |s| s.as_str().to_owned());
+            *self.requests.entry(status).or_insert(0) += 1;
+
+            if let Some(response_time) = result.response_time {
+                let seconds = response_time.as_secs_f64();
+                for (bucket, upper_bound) in METRICS_LATENCY_BUCKETS.iter().enumerate() {
+                    if seconds <= *upper_bound {
+                        self.latency_buckets[bucket] += 1;
+                    }
+                }
+                self.latency_sum += seconds;
+                self.latency_count += 1;
+            }
+        }
+    }
+
+    /// Formats the totals, along with the requests currently in flight, in Prometheus' text exposition format.
+    pub(crate) fn to_prometheus(&self, in_flight: usize) -> String {
+        let mut text = String::from(
+            "# HELP smashit_requests_total Completed requests by status code.\n\
+            # TYPE smashit_requests_total counter\n",
+        );
+        for (status, count) in self.requests.iter().sorted() {
+            text.push_str(&format!(
+                "smashit_requests_total{{status=\"{0}\"}} {1}\n",
+                status, count
+            ));
+        }
+
+        text.push_str(
+            "# HELP smashit_request_duration_seconds Response times of completed requests.\n\
+            # TYPE smashit_request_duration_seconds histogram\n",
+        );
+        for (upper_bound, count) in METRICS_LATENCY_BUCKETS.iter().zip(self.latency_buckets) {
+            text.push_str(&format!(
+                "smashit_request_duration_seconds_bucket{{le=\"{0}\"}} {1}\n",
+                upper_bound, count
+            ));
+        }
+        text.push_str(&format!(
+            "smashit_request_duration_seconds_bucket{{le=\"+Inf\"}} {0}\n\
+            smashit_request_duration_seconds_sum {1}\n\
+            smashit_request_duration_seconds_count {0}\n",
+            self.latency_count, self.latency_sum
+        ));
+
+        text.push_str(&format!(
+            "# HELP smashit_requests_in_flight Requests currently in flight.\n\
+            # TYPE smashit_requests_in_flight gauge\n\
+            smashit_requests_in_flight {0}\n",
+            in_flight
+        ));
+
+        text
+    }
+}
+
+/// Serves the run's metrics to anything that connects, whatever it asks for, for Prometheus to scrape whilst the run is
+/// in progress.
+pub(crate) async fn serve_metrics(listener: TcpListener, state: Arc<RunState>) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            // The request itself doesn't matter but is read so that the client isn't reset whilst still sending it.
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+
+            let body = state
+                .metrics
+                .lock()
+                .unwrap()
+                .to_prometheus(state.in_flight.load(Ordering::SeqCst));
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {0}\r\n\
+                Connection: close\r\n\r\n{1}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Gets the number of requests that a run will send (at most), unless it's going to keep sending them until its
+/// duration is up.
+pub(crate) fn get_total_requests(args: &ParsedArgs) -> Option<usize> {
+    let iterations = match args.virtual_users {
+        Some(virtual_users) => virtual_users.checked_mul(args.iterations),
+        None => Some(args.count as usize),
+    }
+    .filter(|t| *t < i32::MAX as usize)?;
+
+    // Each iteration of a scenario sends a request for each of its steps, unless one of them fails.
+    iterations.checked_mul(args.scenario.as_ref().map_or(1, |s| s.steps.len()))
+}
+
+/// Prints what a run started from the command line does as it goes, being how it was warmed up, its events as they're
+/// recorded and a line of progress at every interval until the returned task is aborted. Runs embedded elsewhere are
+/// left to report their own progress.
+pub(crate) fn print_progress(state: Arc<RunState>, args: Arc<ParsedArgs>) -> JoinHandle<()> {
+    state.printing.store(true, Ordering::SeqCst);
+    if let Some((requests, elapsed)) = state.warmed_up {
+        println!("🔥 Warm-up");
+        println!(
+            "\t{0} requests sent over {1:.1}s, which aren't included in the results\n",
+            requests,
+            elapsed.as_secs_f64()
+        );
+    }
+
+    tokio::spawn(report_progress(state, args))
+}
+
+/// Prints a line of progress at every interval whilst the run is in progress, with how many requests have completed
+/// (out of how many, if that's known), how many failed and the rate and response times since the previous line.
+pub(crate) async fn report_progress(state: Arc<RunState>, args: Arc<ParsedArgs>) {
+    let total = get_total_requests(&args);
+    let mut interval = tokio::time::interval(args.progress_interval);
+    interval.tick().await;
+    let mut previously_failed = 0;
+
+    loop {
+        interval.tick().await;
+
+        let recent = std::mem::take(&mut *state.recently_completed.lock().unwrap());
+        let timings = recent.iter().flatten().copied().sorted().collect_vec();
+        let completed = state.completed.load(Ordering::SeqCst);
+        let failed = state.failed.load(Ordering::SeqCst);
+
+        println!(
+            "\t⏳ {0:.1}s: {1}{2} requests completed, {3} failed ({4} since last time), {5:.1}rps, p50 {6}, p99 {7}",
+            state.started.elapsed().as_secs_f64(),
+            completed,
+            total.map_or(String::new(), |t| format!("/{0}", t)),
+            failed,
+            failed - previously_failed,
+            recent.len() as f64 / args.progress_interval.as_secs_f64(),
+            get_progress_percentile(&timings, 50.0),
+            get_progress_percentile(&timings, 99.0),
+        );
+        previously_failed = failed;
+    }
+}
+
+/// Formats a percentile of the response times since progress was last reported, or a dash if there weren't any.
+pub(crate) fn get_progress_percentile(sorted: &[Duration], percentile: f64) -> String {
+    if sorted.is_empty() {
+        String::from("-")
+    } else {
+        format_latency(get_percentile(sorted, percentile))
+    }
+}
+
+/// Performs the request to the given target for a set of arguments parsed from the command line.
+pub(crate) async fn perform_request(
+    client: Arc<reqwest::Client>,
+    parsed_args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+) -> ResponseStatistics {
+    let client = target.proxy.map_or(&*client, |p| &state.proxy_clients[p]);
+    let oauth2_token = get_oauth2_token(client, &parsed_args, &state).await;
+
+    let _in_flight = InFlightRequest::start(&state);
+
+    let position = target.scenario.as_ref();
+    let mut context = get_template_context(&parsed_args, &state, position.and_then(|p| p.row));
+    if let Some(position) = position {
+        context.variables = position.variables.lock().unwrap().clone();
+    }
+    let url = render_template(&target.url, &context);
+    let mut request = client.request(target.method.clone(), &*url);
+
+    // A scenario's step (or an OpenAPI spec's operation) can have headers of its own, which replace any given on the
+    // command line with the same name.
+    let step = match (position, target.operation) {
+        (Some(position), _) => Some(&position.scenario.steps[position.step]),
+        (None, Some(operation)) => {
+            Some(&parsed_args.openapi.as_ref().unwrap().operations[operation])
+        }
+        (None, None) => None,
+    };
+    let step_headers = step.map_or(&[][..], |s| &s.headers);
+    let headers = parsed_args
+        .headers
+        .iter()
+        .filter(|(h, _)| !step_headers.iter().any(|(s, _)| s.eq_ignore_ascii_case(h)))
+        .chain(step_headers.iter().map(|(h, v)| (h, v)));
+    for (header, value) in headers {
+        request = request.header(header, &*render_template(value, &context));
+    }
+    let cookie_header = target.cookies.as_ref().and_then(|jar| {
+        let url = Url::parse(&url).ok()?;
+        jar.lock().unwrap().get_header(&url)
+    });
+    if let Some(cookie_header) = cookie_header {
+        request = request.header(COOKIE, cookie_header);
+    }
+
+    // A scenario's step can send an Authorization header of its own, which replaces the one from the command line.
+    let step_authorizes = step_headers
+        .iter()
+        .any(|(h, _)| h.eq_ignore_ascii_case("authorization"));
+    let bearer_token = oauth2_token
+        .as_deref()
+        .or(parsed_args.bearer_token.as_deref());
+    match (bearer_token, &parsed_args.basic_auth) {
+        _ if step_authorizes => {}
+        (Some(token), _) => request = request.bearer_auth(token),
+        (None, Some((username, password))) => {
+            request = request.basic_auth(username, Some(password))
+        }
+        (None, None) => {}
+    }
+
+    #[cfg(feature = "http3")]
+    if parsed_args.http_version == HttpVersionPreference::Http3 {
+        request = request.version(Version::HTTP_3);
+    }
+
+    let rendered_body = match (step, &parsed_args.body_template) {
+        (Some(step), _) => step
+            .body
+            .as_ref()
+            .map(|b| Bytes::from(render_template(b, &context).into_owned())),
+        (None, Some(template)) => Some(Bytes::from(
+            render_template(template, &context).into_owned(),
+        )),
+        (None, None) if !parsed_args.form.is_empty() => {
+            let (content_type, body) = get_multipart_body(&parsed_args.form, &context);
+            request = request.header(CONTENT_TYPE, content_type);
+            Some(body)
+        }
+        (None, None) if !parsed_args.urlencoded_form.is_empty() => Some(Bytes::from(
+            get_urlencoded_body(&parsed_args.urlencoded_form, &context),
+        )),
+        (None, None) => None,
+    };
+    let body = match (rendered_body, parsed_args.body_encoding) {
+        (Some(body), Some(encoding)) => Some(Bytes::from(compress_body(encoding, &body))),
+        (Some(body), None) => Some(body),
+        // A body that's the same for every request has already been compressed, if it's to be.
+        (None, _) => parsed_args.body.clone(),
+    };
+    let mut sent_bytes = 0;
+    if let Some(body) = body {
+        sent_bytes = body.len();
+        request = request.body(body);
+    }
+
+    let is_debugged = parsed_args.debug_requests > 0
+        && state.debugged.fetch_add(1, Ordering::SeqCst) < parsed_args.debug_requests;
+    let debugged_request = is_debugged
+        .then(|| request.try_clone()?.build().ok())
+        .flatten()
+        .map(|r| format_debugged_request(&r));
+
+    let before_wait = Instant::now();
+    let _connection_slots = acquire_connection_slots(&parsed_args, &state, &url).await;
+    let connection_wait = (parsed_args.max_connections.is_some()
+        || parsed_args.max_connections_per_host.is_some())
+    .then(|| before_wait.elapsed());
+
+    // The request is only timed from once it has its connection slots, as waiting for them is reported on its own.
+    let before_request = Instant::now();
+    let sent_at = SystemTime::now();
+
+    let mut statistics = ResponseStatistics {
+        method: target.method,
+        url: target.url,
+        proxy: target.proxy,
+        step: position.map(|p| p.step),
+        sent_at: Some(sent_at),
+        sent_bytes,
+        connection_wait,
+        ..Default::default()
+    };
+
+    #[cfg(unix)]
+    let result = match (&state.unix_client, &state.timed_client) {
+        (Some(unix_client), _) => send_over_hyper(unix_client, request).await,
+        (None, Some(timed_client)) => send_over_hyper(timed_client, request).await,
+        (None, None) => request.send().await.map_err(|e| e.into()),
+    };
+    #[cfg(not(unix))]
+    let result = match &state.timed_client {
+        Some(timed_client) => send_over_hyper(timed_client, request).await,
+        None => request.send().await.map_err(|e| e.into()),
+    };
+
+    let result = match result {
+        Ok(r) => r,
+        Err(e) => {
+            if let Some(debugged_request) = &debugged_request {
+                print_debugged_exchange(debugged_request, &format!("\t< {0}", e));
+            }
+            statistics.error = Some(e.to_string());
+            statistics.error_category = Some(get_error_category(&*e));
+            return statistics;
+        }
+    };
+    statistics.first_byte_time = Some(before_request.elapsed());
+
+    let status = result.status();
+    let content_encoding = result
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|e| e.to_str().ok())
+        .map(|e| e.trim().to_ascii_lowercase());
+
+    statistics.status_code = Some(status);
+    statistics.version = Some(result.version());
+    statistics.local_addr = result
+        .extensions()
+        .get::<HttpInfo>()
+        .map(|i| i.local_addr());
+    if let Some(timings) = result.extensions().get::<ConnectionTimings>() {
+        statistics.local_addr = Some(timings.local_addr);
+        if state.timed_connections.lock().unwrap().insert(timings.id) {
+            statistics.connection_timings = Some(*timings);
+        }
+    }
+    if parsed_args.grpc.is_some() {
+        // Calls that fail before sending a response message have their status in the headers, and the rest in the
+        // trailers.
+        let status_headers = match result.headers().contains_key("grpc-status") {
+            true => Some(result.headers()),
+            false => result.extensions().get::<Trailers>().map(|t| &t.0),
+        };
+        let get_header = |name| status_headers?.get(name)?.to_str().ok();
+        statistics.grpc_status = get_header("grpc-status").and_then(|s| s.parse().ok());
+        statistics.error = match statistics.grpc_status {
+            Some(0) => None,
+            Some(grpc_status) => Some(
+                format!(
+                    "gRPC status {0} {1}",
+                    get_grpc_status_name(grpc_status),
+                    get_header("grpc-message").unwrap_or_default()
+                )
+                .trim_end()
+                .to_string(),
+            ),
+            None => Some(String::from(
+                "The response had no gRPC status in its headers or trailers",
+            )),
+        };
+    }
+    statistics.edge_headers = get_edge_headers(result.headers());
+    if let (Some(jar), true) = (&target.cookies, parsed_args.cookies) {
+        jar.lock().unwrap().store(result.headers(), result.url());
+    }
+    if parsed_args.max_pages > 1 && parsed_args.next_page_json.is_none() {
+        statistics.next_page = get_next_link(result.headers(), result.url());
+    }
+    statistics.clock_skew_millis =
+        get_clock_skew_millis(result.headers(), sent_at, before_request.elapsed());
+    // Headers are extracted from once the body has been read (and the step found to be successful), which consumes them.
+    let extraction_headers = step
+        .filter(|s| !s.extractions.is_empty())
+        .map(|_| result.headers().clone());
+    statistics.content_type = result
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .map(|t| t.split(';').next().unwrap_or(t).trim().to_ascii_lowercase());
+
+    let debugged_response = debugged_request
+        .as_ref()
+        .map(|_| format_debugged_response(result.version(), status, result.headers()));
+
+    // Bodies of unsuccessful responses are still read, as their size and type (e.g. an HTML error page) are telling.
+    let bytes = match result.bytes().await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            if let (Some(request), Some(response)) = (&debugged_request, &debugged_response) {
+                print_debugged_exchange(request, &format!("{0}\t< {1}", response, e));
+            }
+            statistics.response_time = Some(before_request.elapsed());
+            statistics.error = Some(e.to_string());
+            statistics.error_category = Some(match get_error_category(&e) {
+                ErrorCategory::Timeout => ErrorCategory::Timeout,
+                _ => ErrorCategory::BodyRead,
+            });
+            return statistics;
+        }
+    };
+
+    if let (Some(request), Some(response)) = (&debugged_request, &debugged_response) {
+        let body = get_body_text(content_encoding.as_deref(), &bytes);
+        print_debugged_exchange(
+            request,
+            &format!("{0}{1}", response, format_debugged_body('<', &body)),
+        );
+    }
+
+    statistics.is_success = is_expected_status(&parsed_args.expect_status, status)
+        && (parsed_args.grpc.is_none() || statistics.grpc_status == Some(0));
+    statistics.response_time = Some(before_request.elapsed());
+    statistics.transfer_bytes = Some(bytes.len());
+    // Decompressing is timed on its own, as it's the client's work rather than the server's.
+    let before_decompressing = Instant::now();
+    statistics.decompressed_bytes = content_encoding
+        .as_deref()
+        .and_then(|e| get_decompressed_size(e, &bytes));
+    statistics.decompression_time = statistics
+        .decompressed_bytes
+        .map(|_| before_decompressing.elapsed());
+    statistics.content_encoding = content_encoding.clone();
+
+    if statistics.is_success && !parsed_args.body_assertions.is_empty() {
+        let body = get_body_text(content_encoding.as_deref(), &bytes);
+
+        statistics.failed_assertion = parsed_args
+            .body_assertions
+            .iter()
+            .position(|a| !a.is_satisfied_by(&body));
+        if let Some(failed) = statistics.failed_assertion {
+            statistics.is_success = false;
+            statistics.error = Some(format!(
+                "body assertion failed: {0}",
+                parsed_args.body_assertions[failed]
+            ));
+        }
+    }
+
+    if let (Some(step), Some(position), Some(headers), true) =
+        (step, position, &extraction_headers, statistics.is_success)
+    {
+        let body = get_body_text(content_encoding.as_deref(), &bytes);
+        match extract_variables(&step.extractions, headers, &body) {
+            Ok(extracted) => position.variables.lock().unwrap().extend(extracted),
+            Err(e) => {
+                statistics.is_success = false;
+                statistics.failed_extraction = true;
+                statistics.error = Some(e);
+            }
+        }
+    }
+
+    if !statistics.is_success {
+        let body = get_body_text(content_encoding.as_deref(), &bytes);
+        state.sample_failure_body(status.as_u16(), &body);
+        statistics.next_page = None;
+        return statistics;
+    }
+
+    if let (Some(path), true) = (&parsed_args.next_page_json, parsed_args.max_pages > 1) {
+        statistics.next_page =
+            get_next_page_from_json(&bytes, path, &statistics.url, &parsed_args.cursor_param);
+    }
+
+    statistics
+}
+
+/// Builds a multipart/form-data body from the fields of a form, evaluating the placeholders in its text, giving the
+/// content type to send it with. Each body has a boundary of its own, as a fixed one could appear in an uploaded file.
+pub(crate) fn get_multipart_body(form: &[FormField], context: &TemplateContext) -> (String, Bytes) {
+    let boundary = format!("smashit-{0:032x}", rand::random::<u128>());
+    let mut body = Vec::new();
+
+    for field in form {
+        body.extend_from_slice(format!("--{0}\r\n", boundary).as_bytes());
+        // Quotes and line breaks can't appear in the names, so they're escaped as browsers do.
+        let name = escape_form_name(&field.name);
+        match &field.value {
+            FormValue::Text(value) => {
+                body.extend_from_slice(
+                    format!("Content-Disposition: form-data; name=\"{0}\"\r\n\r\n", name)
+                        .as_bytes(),
+                );
+                body.extend_from_slice(render_template(value, context).as_bytes());
+            }
+            FormValue::File {
+                file_name,
+                content_type,
+                contents,
+            } => {
+                body.extend_from_slice(
+                    format!(
+                        "Content-Disposition: form-data; name=\"{0}\"; filename=\"{1}\"\r\nContent-Type: {2}\r\n\r\n",
+                        name,
+                        escape_form_name(file_name),
+                        content_type
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(contents);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{0}--\r\n", boundary).as_bytes());
+
+    (
+        format!("multipart/form-data; boundary={0}", boundary),
+        Bytes::from(body),
+    )
+}
+
+/// Builds an application/x-www-form-urlencoded body from the fields of a form, evaluating the placeholders in them before
+/// they're encoded.
+pub(crate) fn get_urlencoded_body(form: &[(String, String)], context: &TemplateContext) -> String {
+    form.iter()
+        .map(|(name, value)| {
+            format!(
+                "{0}={1}",
+                percent_encode(&render_template(name, context)),
+                percent_encode(&render_template(value, context))
+            )
+        })
+        .join("&")
+}
+
+/// Escapes the name of a field or file in a multipart/form-data body.
+pub(crate) fn escape_form_name(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Fetches an access token for the OAuth2 client with the client credentials grant, authenticating with its ID and
+/// secret over basic auth.
+pub(crate) async fn fetch_oauth2_token(
+    client: &reqwest::Client,
+    oauth2: &OAuth2Client,
+) -> Result<OAuth2Token, String> {
+    let mut form = vec![("grant_type", "client_credentials")];
+    if let Some(scope) = &oauth2.scope {
+        form.push(("scope", scope));
+    }
+
+    let response = client
+        .post(&oauth2.token_url)
+        .basic_auth(&oauth2.client_id, Some(&oauth2.client_secret))
+        .header(ACCEPT, "application/json")
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "the token endpoint responded with {0}",
+            response.status()
+        ));
+    }
+    let fetched_at = Instant::now();
+    let token: OAuth2TokenResponse = response.json().await.map_err(|e| e.to_string())?;
+
+    Ok(OAuth2Token {
+        access_token: token.access_token,
+        refresh_at: token
+            .expires_in
+            .map(|e| fetched_at + Duration::from_secs(e).mul_f64(0.9)),
+    })
+}
+
+/// Gets the access token to send with a request, if there's an OAuth2 client, first fetching a new one if it's time to.
+/// Whilst one request is fetching it, the others wait for it rather than fetching their own.
+pub(crate) async fn get_oauth2_token(
+    client: &reqwest::Client,
+    args: &ParsedArgs,
+    state: &RunState,
+) -> Option<String> {
+    let (oauth2, token) = (args.oauth2.as_ref()?, state.oauth2_token.as_ref()?);
+    let mut token = token.lock().await;
+
+    if token.refresh_at.is_some_and(|r| Instant::now() >= r) {
+        match fetch_oauth2_token(client, oauth2).await {
+            Ok(fetched) => {
+                *token = fetched;
+                state.record_event(String::from("Fetched a new OAuth2 access token"));
+            }
+            Err(e) => {
+                // The current token may still be valid for a while, so it's kept until a new one can be fetched.
+                token.refresh_at = Some(Instant::now() + OAUTH2_RETRY_DELAY);
+                state.record_event(format!("Failed to fetch a new OAuth2 access token: {0}", e));
+            }
+        }
+    }
+
+    Some(token.access_token.clone())
+}
+
+/// Waits for a connection to the URL's host to become free, if the number of them is limited, returning the slots
+/// taken up by the request, which are freed up when they're dropped.
+pub(crate) async fn acquire_connection_slots(
+    parsed_args: &ParsedArgs,
+    state: &RunState,
+    url: &str,
+) -> Vec<OwnedSemaphorePermit> {
+    let mut slots = vec![];
+
+    if let Some(max) = parsed_args.max_connections_per_host {
+        let origin = Url::parse(url)
+            .map(|u| u.origin().ascii_serialization())
+            .unwrap_or_default();
+        let host_slots = state
+            .host_connection_slots
+            .lock()
+            .unwrap()
+            .entry(origin)
+            .or_insert_with(|| Arc::new(Semaphore::new(max)))
+            .clone();
+        slots.push(host_slots.acquire_owned().await.unwrap());
+    }
+    if let Some(connection_slots) = &state.connection_slots {
+        slots.push(connection_slots.clone().acquire_owned().await.unwrap());
+    }
+
+    slots
+}
+
+/// Performs the request, giving up on it if it takes longer than the timeout (if there is one), in which case all that
+/// is known about it is that it timed out.
+pub(crate) async fn perform_request_with_timeout(
+    client: Arc<reqwest::Client>,
+    parsed_args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+) -> ResponseStatistics {
+    let Some(timeout) = parsed_args.timeout else {
+        return perform_request(client, parsed_args, state, target).await;
+    };
+
+    let sent_at = SystemTime::now();
+    let (method, url, proxy) = (target.method.clone(), target.url.clone(), target.proxy);
+    match tokio::time::timeout(timeout, perform_request(client, parsed_args, state, target)).await {
+        Ok(statistics) => statistics,
+        Err(_) => ResponseStatistics {
+            method,
+            url,
+            proxy,
+            sent_at: Some(sent_at),
+            error: Some(format!(
+                "request timed out after {0}ms",
+                timeout.as_millis()
+            )),
+            timed_out: true,
+            error_category: Some(ErrorCategory::Timeout),
+            ..Default::default()
+        },
+    }
+}
+
+/// Sends a request over one of smashit's own connections (to a Unix domain socket, or timed), which reqwest can't do
+/// itself, turning its response back into one of reqwest's. The bodies of gRPC responses are read here, as their
+/// trailers (which reqwest can't read) are then kept in the response's extensions.
+pub(crate) async fn send_over_hyper<C: Connect + Clone + Send + Sync + 'static>(
+    client: &hyper::Client<C>,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, SendError> {
+    let request = request.build()?;
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .unwrap_or_default();
+
+    let mut hyper_request = hyper::Request::builder()
+        .method(request.method().clone())
+        .uri(request.url().as_str())
+        .body(hyper::Body::from(Bytes::copy_from_slice(body)))?;
+    *hyper_request.headers_mut() = request.headers().clone();
+    hyper_request
+        .headers_mut()
+        .entry(ACCEPT)
+        .or_insert(HeaderValue::from_static("*/*"));
+
+    // The response keeps the URL it came from for resolving relative links against, which only a builder can set.
+    let (mut parts, mut body) = client.request(hyper_request).await?.into_parts();
+    let (url_parts, _) = hyper::http::Response::builder()
+        .url(request.url().clone())
+        .body(())?
+        .into_parts();
+    parts.extensions.extend(url_parts.extensions);
+
+    let is_grpc = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .is_some_and(|t| t.starts_with("application/grpc"));
+    if is_grpc {
+        let bytes = hyper::body::to_bytes(&mut body).await?;
+        if let Some(trailers) = body.trailers().await? {
+            parts.extensions.insert(Trailers(trailers));
+        }
+        body = hyper::Body::from(bytes);
+    }
+
+    Ok(reqwest::Response::from(hyper::Response::from_parts(
+        parts, body,
+    )))
+}
+
+/// Represents the trailers that a response was sent after its body, as they're kept in its extensions by
+/// `send_over_hyper`.
+pub(crate) struct Trailers(pub(crate) HeaderMap);
+
+/// Represents a failure to send a request, whether over reqwest or the Unix domain socket, keeping its underlying
+/// causes so that it can be categorised.
+pub(crate) type SendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Works out what kind of failure an error is by walking through its causes, from the I/O errors of connections
+/// being refused or reset, to the messages of the DNS and TLS errors that are only known by them.
+pub(crate) fn get_error_category(error: &(dyn std::error::Error + 'static)) -> ErrorCategory {
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        if let Some(io_error) = error.downcast_ref::<std::io::Error>() {
+            match io_error.kind() {
+                std::io::ErrorKind::ConnectionRefused => return ErrorCategory::ConnectionRefused,
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::UnexpectedEof => return ErrorCategory::ConnectionReset,
+                std::io::ErrorKind::TimedOut => return ErrorCategory::Timeout,
+                _ => {}
+            }
+        }
+        if error
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_timeout())
+        {
+            return ErrorCategory::Timeout;
+        }
+
+        let message = error.to_string().to_ascii_lowercase();
+        if message.starts_with("dns error") || message.contains("failed to lookup address") {
+            return ErrorCategory::Dns;
+        }
+        if ["tls", "ssl", "certificate", "handshake"]
+            .iter()
+            .any(|m| message.contains(m))
+        {
+            return ErrorCategory::Tls;
+        }
+
+        cause = error.source();
+    }
+
+    ErrorCategory::Other
+}
+
+/// Checks whether the target's request can be sent more than once, by retrying or hedging it, which it can if its method
+/// is idempotent or sending requests with methods that aren't has been explicitly allowed, either for every request or
+/// for the scenario's step that it's for.
+pub(crate) fn can_send_again(parsed_args: &ParsedArgs, target: &Target) -> bool {
+    target.method.is_idempotent()
+        || parsed_args.retry_non_idempotent
+        || target
+            .scenario
+            .as_ref()
+            .is_some_and(|p| p.scenario.steps[p.step].retry_non_idempotent)
+}
+
+/// Performs the request, hedging it if a hedge delay was given and it can be sent more than once.
+pub(crate) async fn perform_request_with_hedging(
+    client: Arc<reqwest::Client>,
+    parsed_args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+) -> ResponseStatistics {
+    match parsed_args.hedge_after {
+        Some(hedge_after) if can_send_again(&parsed_args, &target) => {
+            perform_hedged_request(client, parsed_args, state, target, hedge_after).await
+        }
+        _ => perform_request_with_timeout(client, parsed_args, state, target).await,
+    }
+}
+
+/// Performs the request, retrying it whilst it fails to get a response at all (e.g. the connection was refused or reset)
+/// or gets one of the status codes to retry on, up to the number of retries given and backing off exponentially between
+/// them if asked to. Methods that aren't idempotent, such as POST, are only retried if they have been
+/// explicitly allowed to be (for every request or the scenario's step), so that the load test can't create the same
+/// resource twice.
+pub(crate) async fn perform_request_with_retries(
+    client: Arc<reqwest::Client>,
+    parsed_args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+) -> ResponseStatistics {
+    let retries = if can_send_again(&parsed_args, &target) {
+        parsed_args.retries
+    } else {
+        0
+    };
+
+    let mut attempt = 0;
+    let mut first_attempt_success = false;
+    loop {
+        let mut statistics = perform_request_with_hedging(
+            client.clone(),
+            parsed_args.clone(),
+            state.clone(),
+            target.clone(),
+        )
+        .await;
+        if attempt == 0 {
+            first_attempt_success = statistics.is_success;
+        }
+        statistics.retries = attempt;
+        statistics.first_attempt_success = first_attempt_success;
+
+        let should_retry = match statistics.status_code {
+            Some(status_code) => parsed_args.retry_on.contains(&status_code),
+            None => true,
+        };
+        if !should_retry || attempt == retries {
+            statistics.completed_at = Some(state.started.elapsed());
+            return statistics;
+        }
+
+        if let Some(backoff) = parsed_args.retry_backoff {
+            tokio::time::sleep(backoff.saturating_mul(2u32.saturating_pow(attempt as u32))).await;
+        }
+
+        attempt += 1;
+    }
+}
+
+/// Performs the request and then walks through the pages that follow it, one request at a time, until there is no next
+/// page or the maximum number of pages has been requested.
+pub(crate) async fn perform_paginated_requests(
+    client: Arc<reqwest::Client>,
+    parsed_args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    mut target: Target,
+) -> Vec<ResponseStatistics> {
+    let mut results = vec![];
+
+    for page in 1..=parsed_args.max_pages {
+        let mut statistics = perform_request_with_retries(
+            client.clone(),
+            parsed_args.clone(),
+            state.clone(),
+            target.clone(),
+        )
+        .await;
+        statistics.page = Some(page);
+
+        let next_page = statistics.next_page.take();
+        results.push(statistics);
+
+        match next_page {
+            Some(url) => target.url = url,
+            None => break,
+        }
+    }
+
+    results
+}
+
+/// Gets the URL of the next page from an RFC 8288 (formerly RFC 5988) Link header, resolved against the URL of the
+/// response it came from.
+pub(crate) fn get_next_link(headers: &HeaderMap, response_url: &Url) -> Option<String> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|l| l.to_str().ok())
+        .flat_map(|l| l.split(','))
+        .find_map(|link| {
+            let (url, params) = link.trim().split_once(';')?;
+            let is_next = params.split(';').any(|p| {
+                p.trim()
+                    .strip_prefix("rel=")
+                    .map(|r| {
+                        r.trim_matches('"')
+                            .split_whitespace()
+                            .any(|r| r.eq_ignore_ascii_case("next"))
+                    })
+                    .unwrap_or(false)
+            });
+
+            if is_next {
+                let url = url.trim().strip_prefix('<')?.strip_suffix('>')?;
+                response_url.join(url).ok().map(String::from)
+            } else {
+                None
+            }
+        })
+}
+
+/// Gets the next page from the value at the given path in a JSON response body. URLs (absolute or relative) are
+/// followed as they are, whereas any other value is treated as a cursor and set as a query parameter on the current URL.
+pub(crate) fn get_next_page_from_json(
+    body: &[u8],
+    path: &str,
+    current_url: &str,
+    cursor_param: &str,
+) -> Option<String> {
+    let body: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let next = match get_json_path(&body, path)? {
+        serde_json::Value::String(s) if !s.is_empty() => s.clone(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => return None,
+    };
+
+    let mut current_url = Url::parse(current_url).ok()?;
+    if next.contains('/') {
+        return current_url.join(&next).ok().map(String::from);
+    }
+
+    let query = current_url
+        .query_pairs()
+        .filter(|(k, _)| k != cursor_param)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect_vec();
+    current_url
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair(cursor_param, &next);
+
+    Some(current_url.to_string())
+}
+
+/// Gets the value at a dotted path in a JSON value, such as `$.meta.next` or `data.0.id`, where numeric segments index
+/// into arrays. The leading `$.` is optional.
+pub(crate) fn get_json_path<'a>(
+    value: &'a serde_json::Value,
+    path: &str,
+) -> Option<&'a serde_json::Value> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+
+    path.split('.')
+        .filter(|s| !s.is_empty())
+        .try_fold(value, |value, segment| match value {
+            serde_json::Value::Array(a) => a.get(segment.parse::<usize>().ok()?),
+            _ => value.get(segment),
+        })
+}
+
+/// Formats a request for --debug as it's about to be sent, with its headers and body, in the style of `curl -v`.
+pub(crate) fn format_debugged_request(request: &reqwest::Request) -> String {
+    let mut formatted = format!(
+        "\t> {0} {1}\n",
+        request.method(),
+        redact_url(request.url().as_str())
+    );
+    formatted.push_str(&format_debugged_headers('>', request.headers()));
+
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .unwrap_or_default();
+    formatted.push_str(&format_debugged_body('>', &String::from_utf8_lossy(body)));
+
+    formatted
+}
+
+/// Formats the status line and headers of a response for --debug.
+pub(crate) fn format_debugged_response(
+    version: Version,
+    status: StatusCode,
+    headers: &HeaderMap,
+) -> String {
+    format!(
+        "\t< {0:?} {1}\n{2}",
+        version,
+        status,
+        format_debugged_headers('<', headers)
+    )
+}
+
+/// Formats headers for --debug, one per line, with the values of any that are likely to hold secrets redacted.
+pub(crate) fn format_debugged_headers(direction: char, headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_sensitive_name(name.as_str()) {
+                REDACTED.into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes())
+            };
+            format!("\t{0} {1}: {2}\n", direction, name, value)
+        })
+        .collect()
+}
+
+/// Formats a body for --debug after the blank line that separates it from the headers, cut short if it's too long to
+/// be useful.
+pub(crate) fn format_debugged_body(direction: char, body: &str) -> String {
+    let mut formatted = format!("\t{0}\n", direction);
+    for line in body
+        .chars()
+        .take(DEBUG_BODY_LIMIT)
+        .collect::<String>()
+        .lines()
+    {
+        formatted.push_str(&format!("\t{0} {1}\n", direction, line));
+    }
+
+    let length = body.chars().count();
+    if length > DEBUG_BODY_LIMIT {
+        formatted.push_str(&format!(
+            "\t{0} ... and {1} more characters\n",
+            direction,
+            length - DEBUG_BODY_LIMIT
+        ));
+    }
+
+    formatted
+}
+
+/// Prints a request and its response (or why it didn't get one) for --debug, all at once so that those of requests in
+/// flight at the same time aren't interleaved.
+pub(crate) fn print_debugged_exchange(request: &str, response: &str) {
+    println!("🔍 Debugging a request\n{0}\t\n{1}", request, response);
+}
+
+/// Gets a response's body as text, decompressing it first if it's compressed.
+pub(crate) fn get_body_text(content_encoding: Option<&str>, bytes: &[u8]) -> String {
+    let decompressed = content_encoding.and_then(|e| decompress_body(e, bytes));
+    String::from_utf8_lossy(decompressed.as_deref().unwrap_or(bytes)).into_owned()
+}
+
+/// Extracts a scenario step's variables from its response, failing if any of them can't be found in it as the steps
+/// after it would be sent without them.
+pub(crate) fn extract_variables(
+    extractions: &[Extraction],
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<Vec<(String, String)>, String> {
+    let json = OnceCell::new();
+
+    extractions
+        .iter()
+        .map(|extraction| {
+            let value = match &extraction.source {
+                ExtractionSource::JsonPath(path) => json
+                    .get_or_init(|| serde_json::from_str::<serde_json::Value>(body).ok())
+                    .as_ref()
+                    .and_then(|json| get_json_path(json, path))
+                    .filter(|v| !v.is_null())
+                    .map(|v| match v {
+                        serde_json::Value::String(s) => s.clone(),
+                        v => v.to_string(),
+                    }),
+                ExtractionSource::Header(header) => headers
+                    .get(header)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from),
+                ExtractionSource::Regex(regex) => regex
+                    .captures(body)
+                    .and_then(|c| c.get(1).or_else(|| c.get(0)))
+                    .map(|m| m.as_str().to_owned()),
+            };
+
+            value
+                .map(|value| (extraction.name.clone(), value))
+                .ok_or_else(|| {
+                    format!(
+                        "failed to extract {0} from {1}",
+                        extraction.name, extraction.source
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Waits for the requests that lost a hedged race to complete (or to be given up on), unless the run was stopped early,
+/// recording the response times they had against the requests whose hedges beat them.
+pub(crate) async fn record_unhedged_response_times(
+    results: &mut [ResponseStatistics],
+    state: &RunState,
+) {
+    let hedge_losers = std::mem::take(&mut *state.hedge_losers.lock().unwrap());
+    if state.stop_reason().is_some() {
+        hedge_losers.iter().for_each(JoinHandle::abort);
+    }
+    futures::future::join_all(hedge_losers).await;
+
+    let unhedged_response_times = state.unhedged_response_times.lock().unwrap();
+    for result in results {
+        if let Some(loser) = result.hedge_loser.take() {
+            result.unhedged_response_time = unhedged_response_times.get(&loser).copied();
+        }
+    }
+}
+
+/// Performs the request, firing a duplicate (hedge) request if the first hasn't completed within the hedge delay and
+/// taking whichever completes first. A hedge that loses is aborted, whereas a primary request that loses is left to
+/// complete in the background (for a while, before it's aborted) so that the latency the request would have had without
+/// hedging can be reported alongside it once the run is over.
+pub(crate) async fn perform_hedged_request(
+    client: Arc<reqwest::Client>,
+    parsed_args: Arc<ParsedArgs>,
+    state: Arc<RunState>,
+    target: Target,
+    hedge_after: Duration,
+) -> ResponseStatistics {
+    let before_request = Instant::now();
+    let mut primary = tokio::spawn(perform_request_with_timeout(
+        client.clone(),
+        parsed_args.clone(),
+        state.clone(),
+        target.clone(),
+    ));
+
+    let mut statistics = match tokio::time::timeout(hedge_after, &mut primary).await {
+        Ok(result) => {
+            let mut statistics = result.unwrap();
+            statistics.unhedged_response_time = statistics.response_time;
+            return statistics;
+        }
+        Err(_) => {
+            let mut hedge = tokio::spawn(perform_request_with_timeout(
+                client,
+                parsed_args,
+                state.clone(),
+                target,
+            ));
+
+            tokio::select! {
+                result = &mut primary => {
+                    // Dropping the hedge's handle would leave it running, holding on to its connection.
+                    hedge.abort();
+                    let mut statistics = result.unwrap();
+                    statistics.unhedged_response_time = statistics.response_time;
+                    statistics
+                }
+                result = &mut hedge => {
+                    let mut statistics = result.unwrap();
+                    statistics.hedge_won = true;
+                    statistics.response_time = statistics.response_time.map(|_| before_request.elapsed());
+
+                    let mut hedge_losers = state.hedge_losers.lock().unwrap();
+                    let loser = hedge_losers.len();
+                    statistics.hedge_loser = Some(loser);
+                    let loser_state = state.clone();
+                    hedge_losers.push(tokio::spawn(async move {
+                        let unhedged = tokio::time::timeout(HEDGE_LOSER_TIMEOUT, &mut primary)
+                            .await
+                            .ok()
+                            .and_then(|r| r.ok()?.response_time);
+                        primary.abort();
+                        if let Some(unhedged) = unhedged {
+                            loser_state
+                                .unhedged_response_times
+                                .lock()
+                                .unwrap()
+                                .insert(loser, unhedged);
+                        }
+                    }));
+                    statistics
+                }
+            }
+        }
+    };
+
+    statistics.hedged = true;
+    statistics
+}
+
+/// The content encodings that responses can be decompressed from, which can be asked for with --accept-encoding.
+pub(crate) const SUPPORTED_CONTENT_ENCODINGS: [&str; 4] = ["gzip", "deflate", "br", "zstd"];
+
+/// Gets a reader that decompresses a response body encoded with the given content encoding, if it's supported.
+pub(crate) fn get_decoder<'a>(
+    content_encoding: &str,
+    body: &'a [u8],
+) -> Option<Box<dyn Read + 'a>> {
+    match content_encoding {
+        "gzip" | "x-gzip" => Some(Box::new(GzDecoder::new(body))),
+        "deflate" => Some(Box::new(ZlibDecoder::new(body))),
+        "br" => Some(Box::new(brotli::Decompressor::new(body, 4096))),
+        "zstd" => Some(Box::new(zstd::stream::read::Decoder::new(body).ok()?)),
+        _ => None,
+    }
+}
+
+/// Decompresses a response body encoded with the given content encoding, returning its decompressed size. None is
+/// returned if the encoding isn't supported or the body couldn't be decompressed.
+pub(crate) fn get_decompressed_size(content_encoding: &str, body: &[u8]) -> Option<usize> {
+    std::io::copy(
+        &mut get_decoder(content_encoding, body)?,
+        &mut std::io::sink(),
+    )
+    .ok()
+    .map(|size| size as usize)
+}
+
+/// Compresses a request body with the given content encoding, which is one of those that are supported. Brotli uses a
+/// middling quality, as its highest is far too slow to compress a body for every request with.
+pub(crate) fn compress_body(content_encoding: &str, body: &[u8]) -> Vec<u8> {
+    match content_encoding {
+        "gzip" => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        }
+        "deflate" => {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(body).unwrap();
+            encoder.finish().unwrap()
+        }
+        "br" => {
+            let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+            encoder.write_all(body).unwrap();
+            encoder.into_inner()
+        }
+        "zstd" => zstd::encode_all(body, 0).unwrap(),
+        _ => body.to_vec(),
+    }
+}
+
+/// Decompresses a response body encoded with the given content encoding. None is returned if the encoding isn't
+/// supported or the body couldn't be decompressed.
+pub(crate) fn decompress_body(content_encoding: &str, body: &[u8]) -> Option<Vec<u8>> {
+    let mut decompressed = vec![];
+    get_decoder(content_encoding, body)?
+        .read_to_end(&mut decompressed)
+        .ok()?;
+
+    Some(decompressed)
+}
+
+/// Pulls out the values of any headers which identify the server, proxy or CDN edge location that served a response.
+/// Cloudflare's ray identifiers are unique per request, so only the trailing POP code is kept from them.
+pub(crate) fn get_edge_headers(headers: &HeaderMap) -> Vec<(&'static str, String)> {
+    let mut edge_headers = vec![];
+
+    for name in EDGE_HEADERS {
+        for value in headers.get_all(name) {
+            let value = match value.to_str() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            let value = if name == "cf-ray" {
+                value.rsplit('-').next().unwrap_or(value)
+            } else {
+                value
+            };
+
+            edge_headers.push((name, value.to_owned()));
+        }
+    }
+
+    edge_headers
+}
+
+/// Estimates how far ahead (positive) or behind (negative) the server's clock is compared to the local clock by
+/// comparing its Date header with the local time halfway through the request. Date headers only have a resolution of
+/// one second, so half a second is added to the server's time to centre the estimate.
+pub(crate) fn get_clock_skew_millis(
+    headers: &HeaderMap,
+    sent_at: SystemTime,
+    round_trip: Duration,
+) -> Option<i64> {
+    let server_time = headers
+        .get(DATE)
+        .and_then(|d| d.to_str().ok())
+        .and_then(|d| httpdate::parse_http_date(d).ok())?
+        + Duration::from_millis(500);
+    let local_time = sent_at + round_trip / 2;
+
+    match server_time.duration_since(local_time) {
+        Ok(ahead) => Some(ahead.as_millis() as i64),
+        Err(behind) => Some(-(behind.duration().as_millis() as i64)),
+    }
+}
+
+/// Represents a load test that is ready to run, with the clients that its requests are sent through set up.
+pub struct LoadTest {
+    pub(crate) args: Arc<ParsedArgs>,
+    client: Arc<reqwest::Client>,
+    proxy_clients: Vec<reqwest::Client>,
+    #[cfg(unix)]
+    unix_client: Option<hyper::Client<UnixSocketConnector>>,
+    timed_client: Option<hyper::Client<TimedConnector>>,
+    dns_lookups: Arc<Mutex<Vec<Duration>>>,
+    http3_handshake: Option<Duration>,
+    oauth2_token: Option<Arc<AsyncMutex<OAuth2Token>>>,
+    request_log: Option<Arc<Mutex<BufWriter<File>>>>,
+    /// What the run is followed by besides its options, if it was built with a [`LoadTestBuilder`].
+    pub(crate) hooks: RunHooks,
+}
+
+impl LoadTest {
+    /// Sets up the clients for a load test, checking whether the server supports HTTP/3, discovering the URLs in its
+    /// sitemap and fetching an OAuth2 access token first if any were asked for.
+    pub async fn new(config: TestConfig) -> Result<LoadTest, String> {
+        let mut parsed_args = config.args;
+        let dns_lookups = Arc::new(Mutex::new(vec![]));
+
+        #[cfg(feature = "http3")]
+        let http3 = check_http3(&mut parsed_args, &dns_lookups).await;
+        #[cfg(not(feature = "http3"))]
+        let http3: Option<(reqwest::Client, Duration)> = None;
+        let http3_handshake = http3.as_ref().map(|h| h.1);
+
+        let client = http3
+            .map_or_else(
+                || get_client_builder(&parsed_args, &dns_lookups).build(),
+                |h| Ok(h.0),
+            )
+            .map_err(|e| format!("Failed to set up the client: {0}", e))?;
+
+        if let Some(sitemap) = &parsed_args.sitemap {
+            parsed_args.targets = get_sitemap_targets(
+                &client,
+                sitemap,
+                &parsed_args.method,
+                parsed_args.sitemap_weighting,
+            )
+            .await;
+
+            if parsed_args.targets.is_empty() {
+                return Err(format!(
+                    "No URLs could be discovered in the sitemap at {0}",
+                    sitemap
+                ));
+            }
+        }
+
+        let oauth2_token = match &parsed_args.oauth2 {
+            Some(oauth2) => Some(Arc::new(AsyncMutex::new(
+                fetch_oauth2_token(&client, oauth2).await.map_err(|e| {
+                    format!(
+                        "Failed to fetch an OAuth2 access token from {0}: {1}",
+                        oauth2.token_url, e
+                    )
+                })?,
+            ))),
+            None => None,
+        };
+
+        let proxy_clients = get_proxy_clients(&parsed_args, &dns_lookups)
+            .map_err(|e| format!("Failed to set up the proxies: {0}", e))?;
+        let timed_client = get_timed_client(&parsed_args, &dns_lookups)
+            .map_err(|e| format!("Failed to set up the timed connections: {0}", e))?;
+        let request_log = match &parsed_args.request_log_path {
+            Some(path) => Some(Arc::new(Mutex::new(BufWriter::new(
+                File::create(path).map_err(|e| {
+                    format!("Failed to create the request log at {0}: {1}", path, e)
+                })?,
+            )))),
+            None => None,
+        };
+
+        Ok(LoadTest {
+            #[cfg(unix)]
+            unix_client: get_unix_client(&parsed_args),
+            args: Arc::new(parsed_args),
+            client: Arc::new(client),
+            proxy_clients,
+            timed_client,
+            dns_lookups,
+            http3_handshake,
+            oauth2_token,
+            request_log,
+            hooks: RunHooks::default(),
+        })
+    }
+
+    /// Runs the load test, warming up first if asked to, and gives the results once every request has completed.
+    pub async fn run(&self) -> Result<TestResults, String> {
+        self.run_watched(|_| {}).await
+    }
+
+    /// Runs the load test as with [`LoadTest::run`], sharing the state of the run with whatever is watching its
+    /// progress as soon as it starts.
+    pub(crate) async fn run_watched(
+        &self,
+        watch: impl FnOnce(Arc<RunState>),
+    ) -> Result<TestResults, String> {
+        let (client, args, dns_lookups) = (&self.client, &self.args, &self.dns_lookups);
+        let sequence = Arc::new(AtomicUsize::new(0));
+        let mut warmed_up = None;
+
+        if args.warmup.is_some() || args.warmup_requests.is_some() {
+            let state = Arc::new(RunState {
+                sequence: sequence.clone(),
+                ..self.get_run_state()
+            });
+            let started = Instant::now();
+            warmed_up = Some((warm_up(client, args, &state).await, started.elapsed()));
+
+            // The lookups made whilst warming up are part of what's being kept out of the results.
+            dns_lookups.lock().unwrap().clear();
+        }
+
+        // Only the run's own requests are logged, not those sent whilst warming up.
+        let state = Arc::new(RunState {
+            request_log: self.request_log.clone(),
+            request_events: Mutex::new(self.hooks.events.clone()),
+            sequence,
+            warmed_up,
+            ..self.get_run_state()
+        });
+        watch(state.clone());
+
+        // Whatever watches over the run is stopped once it's over, so that nothing outlives it when it's one of many.
+        let mut background = vec![
+            tokio::spawn(sample_in_flight_requests(state.clone())),
+            tokio::spawn(record_ramp_events(state.clone(), args.clone())),
+        ];
+
+        if let Some(address) = args.metrics_address {
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|e| format!("Failed to serve metrics on {0}: {1}", address, e))?;
+            background.push(tokio::spawn(serve_metrics(listener, state.clone())));
+        }
+
+        if args.interactive {
+            read_load_controls(client.clone(), args.clone(), state.clone());
+        }
+
+        #[cfg(unix)]
+        background.push(tokio::spawn(record_signal_markers(state.clone())));
+
+        if let Some(path) = &args.pattern_file {
+            background.push(tokio::spawn(watch_pattern_file(
+                path.clone(),
+                state.clone(),
+            )));
+        }
+
+        if let Some(path) = &args.scenario_file {
+            background.push(tokio::spawn(watch_scenario_file(
+                path.clone(),
+                state.clone(),
+            )));
+        }
+
+        if let Some((interval, callback)) = &self.hooks.progress {
+            background.push(tokio::spawn(call_progress_callback(
+                state.clone(),
+                *interval,
+                callback.clone(),
+            )));
+        }
+
+        if let Some(token) = &self.hooks.cancellation {
+            background.push(tokio::spawn(stop_on_cancellation(
+                state.clone(),
+                token.clone(),
+            )));
+        }
+
+        let requests = match args.virtual_users {
+            Some(virtual_users) => (0..virtual_users)
+                .map(|user| {
+                    tokio::spawn(run_virtual_user(
+                        client.clone(),
+                        args.clone(),
+                        state.clone(),
+                        user,
+                    ))
+                })
+                .collect_vec(),
+            None => dispatch_requests(client, args, &state).await,
+        };
+
+        let mut results: Vec<ResponseStatistics> = futures::future::join_all(requests)
+            .await
+            .into_iter()
+            .flat_map(|r| r.unwrap())
+            .collect();
+
+        // Virtual users can be added by live controls until the last of them have finished.
+        loop {
+            let added = {
+                let mut added_virtual_users = state.added_virtual_users.lock().unwrap();
+                let added = added_virtual_users.take().unwrap_or_default();
+                if !added.is_empty() {
+                    *added_virtual_users = Some(vec![]);
+                }
+                added
+            };
+            if added.is_empty() {
+                break;
+            }
+            for user in futures::future::join_all(added).await {
+                results.extend(user.unwrap());
+            }
+        }
+        let elapsed = state.started.elapsed();
+
+        for task in background {
+            task.abort();
+        }
+        record_unhedged_response_times(&mut results, &state).await;
+        state.request_events.lock().unwrap().take();
+
+        if let Some(request_log) = &state.request_log {
+            request_log
+                .lock()
+                .unwrap()
+                .flush()
+                .map_err(|e| format!("Failed to write to the request log: {0}", e))?;
+        }
+
+        Ok(TestResults {
+            results,
+            elapsed,
+            state,
+            args: args.clone(),
+        })
+    }
+
+    /// Sets up the state of a run, sharing the clients (and so the connections they have open) with any other runs.
+    fn get_run_state(&self) -> RunState {
+        get_run_state(
+            &self.args,
+            &self.proxy_clients,
+            #[cfg(unix)]
+            &self.unix_client,
+            &self.timed_client,
+            &self.dns_lookups,
+            self.http3_handshake,
+            &self.oauth2_token,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    /// Sets up the state of a run with the given options, as they'd be given on the command line after the program's
+    /// name.
+    fn get_test_run_state(args: &[&str]) -> RunState {
+        let args = parse_args(
+            std::iter::once("smashit")
+                .chain(args.iter().copied())
+                .map(String::from)
+                .collect(),
+        )
+        .unwrap();
+        get_run_state(
+            &args,
+            &[],
+            #[cfg(unix)]
+            &None,
+            &None,
+            &Arc::new(Mutex::new(vec![])),
+            None,
+            &None,
+        )
+    }
+
+    #[test]
+    fn adjust_concurrency_limit_keeps_at_least_one_request_in_flight() {
+        let state = get_test_run_state(&["-u", "http://localhost/", "-p", "20"]);
+
+        assert_eq!(adjust_concurrency_limit(&state, 4), (20, 24));
+        assert_eq!(adjust_concurrency_limit(&state, -30), (24, 1));
+        assert_eq!(adjust_concurrency_limit(&state, 2), (1, 3));
+    }
+
+    #[test]
+    fn get_dns_query_encodes_the_name_as_labels() {
+        let query = get_dns_query("my-api.com.", DNS_TYPE_AAAA);
+
+        assert_eq!(&query[..12], [0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[12..19], b"\x06my-api");
+        assert_eq!(&query[19..24], b"\x03com\x00");
+        assert_eq!(&query[24..], [0, 28, 0, 1]);
+    }
+
+    #[test]
+    fn get_dns_answers_reads_addresses_through_cnames() {
+        let mut message = get_dns_query("www.my-api.com", DNS_TYPE_A);
+        message[7] = 3;
+        // A CNAME pointing at my-api.com (by a pointer into the question), then an A and an AAAA record for it.
+        message.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xC0, 16]);
+        message.extend_from_slice(&[0xC0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 5]);
+        message.extend_from_slice(&[0xC0, 16, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+        message.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+
+        assert_eq!(
+            get_dns_answers(&message),
+            Some(vec![
+                IpAddr::from([10, 0, 0, 5]),
+                IpAddr::from(Ipv6Addr::LOCALHOST)
+            ])
+        );
+    }
+
+    #[test]
+    fn get_dns_answers_rejects_truncated_messages() {
+        let mut message = get_dns_query("my-api.com", DNS_TYPE_A);
+        message[7] = 1;
+        message.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0]);
+
+        assert_eq!(get_dns_answers(&message), None);
+        assert_eq!(get_dns_answers(&[0, 0, 1]), None);
+    }
+
+    #[test]
+    fn get_next_link_finds_the_next_page_among_the_links() {
+        let response_url = Url::parse("https://my-api.com/users?page=1").unwrap();
+        let mut headers = HeaderMap::new();
+        headers.append(
+            LINK,
+            HeaderValue::from_static(r#"<https://my-api.com/users?page=1>; rel="first""#),
+        );
+        headers.append(
+            LINK,
+            HeaderValue::from_static(
+                r#"</users?page=5>; rel="last", </users?page=2>; rel="next prefetch""#,
+            ),
+        );
+
+        assert_eq!(
+            get_next_link(&headers, &response_url).as_deref(),
+            Some("https://my-api.com/users?page=2")
+        );
+
+        headers.remove(LINK);
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(r#"</users?page=1>; rel="prev""#),
+        );
+        assert_eq!(get_next_link(&headers, &response_url), None);
+    }
+
+    #[test]
+    fn get_next_page_from_json_follows_urls_and_sets_cursors() {
+        let current_url = "https://my-api.com/users?cursor=abc&limit=10";
+
+        assert_eq!(
+            get_next_page_from_json(
+                br#"{"meta": {"next": "def"}}"#,
+                "$.meta.next",
+                current_url,
+                "cursor"
+            )
+            .as_deref(),
+            Some("https://my-api.com/users?limit=10&cursor=def")
+        );
+        assert_eq!(
+            get_next_page_from_json(
+                br#"{"next": "/users?page=2"}"#,
+                "next",
+                current_url,
+                "cursor"
+            )
+            .as_deref(),
+            Some("https://my-api.com/users?page=2")
+        );
+        assert_eq!(
+            get_next_page_from_json(br#"{"next": null}"#, "next", current_url, "cursor"),
+            None
+        );
+    }
+}
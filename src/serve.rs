@@ -0,0 +1,340 @@
+//! Serving an API for starting runs, following their progress and fetching their results as JSON, so that they can be
+//! orchestrated by other tools.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc, Mutex},
+};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{args::*, report::*, runner::*};
+
+/// The port that the API is served on unless it's told otherwise.
+pub(crate) const DEFAULT_SERVE_PORT: u16 = 8080;
+
+/// Represents the body of a request to start a run, with the options to run it with as they'd be given on the command
+/// line.
+#[derive(Deserialize)]
+pub(crate) struct RunRequest {
+    pub(crate) args: Vec<String>,
+}
+
+/// Represents a run started through the API.
+pub(crate) struct ServedRun {
+    pub(crate) id: usize,
+    pub(crate) status: Mutex<ServedRunStatus>,
+}
+
+/// Represents how far a run started through the API has got.
+pub(crate) enum ServedRunStatus {
+    /// The run's clients are being set up, and its sitemap discovered if it has one.
+    Starting,
+    Running(Arc<RunState>, Arc<ParsedArgs>),
+    /// The run has finished, with the headline numbers of its results.
+    Finished(serde_json::Value),
+    Failed(String),
+}
+
+/// The runs that have been started through the API, in the order they were started, with the first's ID being 1.
+pub(crate) type ServedRuns = Arc<Mutex<Vec<Arc<ServedRun>>>>;
+
+/// Serves the API until smashit is stopped, with the runs started through it kept for as long as it's serving. If a
+/// token is given, only requests that send it as a bearer token are handled.
+pub(crate) async fn serve_api(address: SocketAddr, token: Option<String>) {
+    let runs = ServedRuns::default();
+    let token = Arc::new(token);
+    let make_service = make_service_fn(move |_| {
+        let (runs, token) = (runs.clone(), token.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let authorised = is_authorised(&request, token.as_deref());
+                let runs = runs.clone();
+                async move {
+                    match authorised {
+                        true => handle_api_request(request, runs).await,
+                        false => Ok(get_error_response(
+                            StatusCode::UNAUTHORIZED,
+                            "The API's token must be sent as a bearer token in the Authorization header",
+                        )),
+                    }
+                }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            eprintln!("Failed to serve the API on {0}: {1}", address, e);
+            return;
+        }
+    };
+    println!("🛰️ Serving the API on {0}\n", address);
+
+    if let Err(e) = server.await {
+        eprintln!("Failed to keep serving the API: {0}", e);
+    }
+}
+
+/// Handles a request to the API, which can start a run (`POST /runs`), list the runs (`GET /runs`) or get a run's
+/// progress or results (`GET /runs/{id}`).
+pub(crate) async fn handle_api_request(
+    request: Request<Body>,
+    runs: ServedRuns,
+) -> Result<Response<Body>, Infallible> {
+    let path = request.uri().path().trim_matches('/').to_owned();
+    let segments = path.split('/').collect_vec();
+
+    let response = match (request.method(), segments.as_slice()) {
+        (&Method::POST, ["runs"]) => start_served_run(request, &runs).await,
+        (&Method::GET, ["runs"]) => {
+            let runs = runs.lock().unwrap().iter().map(|r| r.to_json()).collect();
+            get_json_response(StatusCode::OK, serde_json::Value::Array(runs))
+        }
+        (&Method::GET, ["runs", id]) => {
+            let run = id
+                .parse::<usize>()
+                .ok()
+                .and_then(|id| runs.lock().unwrap().get(id.wrapping_sub(1)).cloned());
+            match run {
+                Some(run) => get_json_response(StatusCode::OK, run.to_json()),
+                None => get_error_response(StatusCode::NOT_FOUND, "There's no run with that ID"),
+            }
+        }
+        (_, ["runs"] | ["runs", _]) => get_error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Runs can only be started with a POST or fetched with a GET",
+        ),
+        _ => get_error_response(StatusCode::NOT_FOUND, "Not found"),
+    };
+
+    Ok(response)
+}
+
+/// Starts a run with the options in the request's body, responding with its ID straight away without waiting for it
+/// to finish.
+pub(crate) async fn start_served_run(request: Request<Body>, runs: &ServedRuns) -> Response<Body> {
+    let run_request = hyper::body::to_bytes(request.into_body())
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<RunRequest>(&body).ok());
+    let Some(run_request) = run_request else {
+        return get_error_response(
+            StatusCode::BAD_REQUEST,
+            "The body must be a JSON object with the options to run with as its args, e.g. \
+             {\"args\": [\"-u\", \"https://my-api.com/users\", \"-c\", \"100\"]}",
+        );
+    };
+
+    let config = std::iter::once(String::from("smashit"))
+        .chain(run_request.args)
+        .collect_vec();
+
+    // The machine serving the API isn't its client's, so a run can't read or write its files or read from its stdin.
+    if let Some(option) = find_local_file_option(&config) {
+        return get_error_response(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "{0} can't be given to a run started through the API, as it reads or writes files on the machine \
+                 serving it",
+                option
+            ),
+        );
+    }
+
+    let config = TestConfig::parse(config).filter(|c| c.args.workers.is_empty());
+    let Some(config) = config else {
+        return get_error_response(
+            StatusCode::BAD_REQUEST,
+            "The options aren't valid, as smashit --help describes, or are for a run that generates the load from \
+             workers, which can't be started through the API",
+        );
+    };
+
+    let run = {
+        let mut runs = runs.lock().unwrap();
+        let run = Arc::new(ServedRun {
+            id: runs.len() + 1,
+            status: Mutex::new(ServedRunStatus::Starting),
+        });
+        runs.push(run.clone());
+        run
+    };
+    println!("📋 Starting run {0}", run.id);
+    tokio::spawn(perform_served_run(run.clone(), config));
+
+    get_json_response(StatusCode::CREATED, run.to_json())
+}
+
+/// Performs a run started through the API, keeping its status up to date as it goes.
+pub(crate) async fn perform_served_run(run: Arc<ServedRun>, config: TestConfig) {
+    let status = match LoadTest::new(config).await {
+        Ok(load_test) => {
+            let watch = |state| {
+                *run.status.lock().unwrap() =
+                    ServedRunStatus::Running(state, load_test.args.clone())
+            };
+            match load_test.run_watched(watch).await {
+                Ok(test_results) => {
+                    let mut summary = get_json_summary(
+                        &test_results.results,
+                        &test_results.state,
+                        &load_test.args,
+                    );
+                    summary["thresholds_passed"] =
+                        check_thresholds(&test_results.results, &load_test.args).into();
+                    ServedRunStatus::Finished(summary)
+                }
+                Err(e) => ServedRunStatus::Failed(e),
+            }
+        }
+        Err(e) => ServedRunStatus::Failed(e),
+    };
+
+    match &status {
+        ServedRunStatus::Failed(e) => println!("\tRun {0} failed: {1}\n", run.id, e),
+        _ => println!("\tRun {0} finished\n", run.id),
+    }
+    *run.status.lock().unwrap() = status;
+}
+
+impl ServedRun {
+    /// Gets the run's status as JSON, along with its progress whilst it's in progress, its results once it has
+    /// finished or why it failed.
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({ "id": self.id });
+
+        match &*self.status.lock().unwrap() {
+            ServedRunStatus::Starting => json["status"] = "starting".into(),
+            ServedRunStatus::Running(state, args) => {
+                let (completed, elapsed) = (
+                    state.completed.load(Ordering::SeqCst),
+                    state.started.elapsed().as_secs_f64(),
+                );
+                json["status"] = "running".into();
+                json["progress"] = serde_json::json!({
+                    "completed": completed,
+                    "failed": state.failed.load(Ordering::SeqCst),
+                    "total": get_total_requests(args),
+                    "in_flight": state.in_flight.load(Ordering::SeqCst),
+                    "duration_secs": elapsed,
+                    "requests_per_second": completed as f64 / elapsed.max(f64::EPSILON),
+                });
+            }
+            ServedRunStatus::Finished(results) => {
+                json["status"] = "finished".into();
+                json["results"] = results.clone();
+            }
+            ServedRunStatus::Failed(e) => {
+                json["status"] = "failed".into();
+                json["error"] = e.as_str().into();
+            }
+        }
+
+        json
+    }
+}
+
+/// Checks whether a request to the API sent its token as a bearer token, which every request is if it doesn't have one.
+pub(crate) fn is_authorised(request: &Request<Body>, token: Option<&str>) -> bool {
+    token.is_none_or(|token| {
+        request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|given| tokens_match(given, token))
+    })
+}
+
+/// Checks whether a token that was given matches the one expected, taking as long to do so regardless of where they
+/// differ so that how long it took doesn't give any of the expected token away.
+pub(crate) fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Builds a response with a JSON body.
+pub(crate) fn get_json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Builds a response with a JSON body describing what went wrong.
+pub(crate) fn get_error_response(status: StatusCode, error: &str) -> Response<Body> {
+    get_json_response(status, serde_json::json!({ "error": error }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a request to the API, with a bearer token if one is given.
+    fn get_test_request(method: Method, body: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri("/runs");
+        if let Some(token) = token {
+            request = request.header(hyper::header::AUTHORIZATION, format!("Bearer {0}", token));
+        }
+        request.body(Body::from(body.to_owned())).unwrap()
+    }
+
+    #[test]
+    fn tokens_match_only_the_same_token() {
+        assert!(tokens_match("s3cret", "s3cret"));
+        assert!(!tokens_match("s3creT", "s3cret"));
+        assert!(!tokens_match("s3cre", "s3cret"));
+        assert!(!tokens_match("", "s3cret"));
+    }
+
+    #[test]
+    fn is_authorised_needs_the_token_as_a_bearer_token() {
+        let request = |token| get_test_request(Method::GET, "", token);
+
+        assert!(is_authorised(&request(Some("s3cret")), Some("s3cret")));
+        assert!(!is_authorised(&request(Some("guess")), Some("s3cret")));
+        assert!(!is_authorised(&request(None), Some("s3cret")));
+        assert!(is_authorised(&request(None), None));
+
+        let basic = Request::builder()
+            .header(hyper::header::AUTHORIZATION, "Basic s3cret")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!is_authorised(&basic, Some("s3cret")));
+    }
+
+    #[tokio::test]
+    async fn handle_api_request_rejects_runs_that_touch_local_files_or_use_workers() {
+        let runs = ServedRuns::default();
+        let start = |args: &str| {
+            let request = get_test_request(Method::POST, &format!("{{\"args\": {0}}}", args), None);
+            handle_api_request(request, runs.clone())
+        };
+
+        let response = start(r#"["-u", "http://localhost", "--save", "/tmp/run.json"]"#)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response =
+            start(r#"["-u", "http://localhost", "--workers", "a:7000", "--token", "t"]"#)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = start(r#"{"url": "http://localhost"}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(runs.lock().unwrap().is_empty());
+    }
+}
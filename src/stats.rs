@@ -0,0 +1,409 @@
+//! The statistics recorded for each request and those calculated from them.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use histogram::Histogram;
+use itertools::Itertools;
+use reqwest::{Method, StatusCode, Version};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{args::*, report::*, runner::*};
+
+/// Represents different timing bounds calculated from all of the results.
+#[derive(Debug, Clone, Copy)]
+pub struct ResponsesTimings {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    pub std_deviation: Duration,
+    pub fiftieth_percentile: Duration,
+    pub seventy_fifth_percentile: Duration,
+    pub ninetieth_percentile: Duration,
+    pub ninety_ninth_percentile: Duration,
+}
+
+/// ResponseStatistics represents timings, status codes and more pulled out from a request's response.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct ResponseStatistics {
+    #[serde(
+        serialize_with = "serialize_method",
+        deserialize_with = "deserialize_method"
+    )]
+    pub(crate) method: Method,
+    pub(crate) url: String,
+    pub(crate) is_success: bool,
+    #[serde(
+        serialize_with = "serialize_status_code",
+        deserialize_with = "deserialize_status_code"
+    )]
+    pub(crate) status_code: Option<StatusCode>,
+    pub(crate) response_time: Option<Duration>,
+    /// How long it took for the response's headers to arrive, before its body was read.
+    pub(crate) first_byte_time: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_edge_headers")]
+    pub(crate) edge_headers: Vec<(&'static str, String)>,
+    pub(crate) clock_skew_millis: Option<i64>,
+    /// The size of the request's body, as it was sent.
+    pub(crate) sent_bytes: usize,
+    pub(crate) transfer_bytes: Option<usize>,
+    pub(crate) decompressed_bytes: Option<usize>,
+    /// The encoding that the response's body was compressed with, if it was.
+    pub(crate) content_encoding: Option<String>,
+    /// How long it took to decompress the response's body, which isn't part of its response time.
+    pub(crate) decompression_time: Option<Duration>,
+    pub(crate) sent_at: Option<SystemTime>,
+    /// How far into the run the request completed, after any retries.
+    pub(crate) completed_at: Option<Duration>,
+    pub(crate) error: Option<String>,
+    pub(crate) hedged: bool,
+    pub(crate) hedge_won: bool,
+    pub(crate) unhedged_response_time: Option<Duration>,
+    /// The index of the request that this one's hedge beat in the run's hedge losers, until the run is over and the
+    /// loser's response time has been recorded as the unhedged response time.
+    #[serde(skip)]
+    pub(crate) hedge_loser: Option<usize>,
+    pub(crate) page: Option<usize>,
+    pub(crate) next_page: Option<String>,
+    pub(crate) scheduling_delay: Option<Duration>,
+    pub(crate) content_type: Option<String>,
+    /// The gRPC status of the response, if it was a gRPC call, from its headers if it failed before it sent a response
+    /// message and otherwise from its trailers.
+    pub(crate) grpc_status: Option<usize>,
+    /// The HTTP version that was negotiated for the response.
+    #[serde(
+        serialize_with = "serialize_version",
+        deserialize_with = "deserialize_version"
+    )]
+    pub(crate) version: Option<Version>,
+    /// The local address of the connection the response came over, which identifies the connection.
+    pub(crate) local_addr: Option<SocketAddr>,
+    /// How long each phase of opening the connection took, if phase timings were asked for and the request opened it.
+    pub(crate) connection_timings: Option<ConnectionTimings>,
+    /// How long the request queued for a connection to become free, if the number of them is limited.
+    pub(crate) connection_wait: Option<Duration>,
+    pub(crate) retries: usize,
+    /// Whether the first attempt at the request was successful, before any retries.
+    pub(crate) first_attempt_success: bool,
+    /// Whether the request was given up on because it took longer than the timeout.
+    pub(crate) timed_out: bool,
+    /// What kind of failure stopped the request from getting a full response, if one did.
+    pub(crate) error_category: Option<ErrorCategory>,
+    /// The index of the first body assertion that the response failed, if it failed any.
+    pub(crate) failed_assertion: Option<usize>,
+    /// Whether the response was missing one of the variables that its scenario step extracts.
+    #[serde(default)]
+    pub(crate) failed_extraction: bool,
+    pub(crate) ramp: Option<Ramp>,
+    pub(crate) proxy: Option<usize>,
+    /// The index of the scenario's step that the request was for, if it was one of a scenario's steps.
+    pub(crate) step: Option<usize>,
+}
+
+/// Saves a request's method by its name.
+pub(crate) fn serialize_method<S: Serializer>(
+    method: &Method,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(method.as_str())
+}
+
+/// Reads a request's method back from its name.
+pub(crate) fn deserialize_method<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Method, D::Error> {
+    Method::from_bytes(String::deserialize(deserializer)?.as_bytes()).map_err(D::Error::custom)
+}
+
+/// Saves a response's status code as its number.
+pub(crate) fn serialize_status_code<S: Serializer>(
+    status_code: &Option<StatusCode>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    status_code.map(|s| s.as_u16()).serialize(serializer)
+}
+
+/// Reads a response's status code back from its number.
+pub(crate) fn deserialize_status_code<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<StatusCode>, D::Error> {
+    Option::<u16>::deserialize(deserializer)?
+        .map(|s| StatusCode::from_u16(s).map_err(D::Error::custom))
+        .transpose()
+}
+
+/// Saves a response's HTTP version as its name, e.g. `HTTP/1.1`.
+pub(crate) fn serialize_version<S: Serializer>(
+    version: &Option<Version>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    version.map(|v| format!("{0:?}", v)).serialize(serializer)
+}
+
+/// Reads a response's HTTP version back from its name.
+pub(crate) fn deserialize_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Version>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|v| match v.as_str() {
+            "HTTP/0.9" => Ok(Version::HTTP_09),
+            "HTTP/1.0" => Ok(Version::HTTP_10),
+            "HTTP/1.1" => Ok(Version::HTTP_11),
+            "HTTP/2.0" => Ok(Version::HTTP_2),
+            "HTTP/3.0" => Ok(Version::HTTP_3),
+            _ => Err(D::Error::custom(format!("unknown HTTP version {0}", v))),
+        })
+        .transpose()
+}
+
+/// Reads a response's edge headers back, keeping only those that are still looked for.
+pub(crate) fn deserialize_edge_headers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(&'static str, String)>, D::Error> {
+    Ok(Vec::<(String, String)>::deserialize(deserializer)?
+        .into_iter()
+        .filter_map(|(name, value)| Some((*EDGE_HEADERS.iter().find(|h| **h == name)?, value)))
+        .collect())
+}
+
+/// Represents the kinds of failure that can stop a request from getting a full response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum ErrorCategory {
+    Dns,
+    ConnectionRefused,
+    ConnectionReset,
+    Timeout,
+    Tls,
+    BodyRead,
+    Other,
+}
+
+impl fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCategory::Dns => write!(f, "DNS failure"),
+            ErrorCategory::ConnectionRefused => write!(f, "Connection refused"),
+            ErrorCategory::ConnectionReset => write!(f, "Connection reset"),
+            ErrorCategory::Timeout => write!(f, "Timeout"),
+            ErrorCategory::Tls => write!(f, "TLS error"),
+            ErrorCategory::BodyRead => write!(f, "Body read error"),
+            ErrorCategory::Other => write!(f, "Other"),
+        }
+    }
+}
+
+/// Represents the phases at the start and end of a run where the load is ramped up to or down from its target.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Ramp {
+    Up,
+    Down,
+}
+
+/// Response headers that identify which server, proxy or CDN point of presence handled a request.
+pub(crate) const EDGE_HEADERS: [&str; 5] =
+    ["server", "via", "x-served-by", "x-amz-cf-pop", "cf-ray"];
+
+/// The slowest response time, in microseconds, that histograms keep apart from the rest (an hour), with any slower
+/// counted as it.
+pub(crate) const LATENCY_HISTOGRAM_MAX_MICROS: u64 = 3_600_000_000;
+
+/// Gets the value at the given percentile from a sorted list of durations, or zero if it's empty.
+pub(crate) fn get_percentile<T: Copy + Default>(sorted: &[T], percentile: f64) -> T {
+    if sorted.is_empty() {
+        return T::default();
+    }
+
+    let index = ((percentile / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[index - 1]
+}
+
+// Gets the minimum, average, maximum and percentile based timings from the results, with the percentiles kept to the
+// given number of significant figures.
+pub(crate) fn get_timings_from_results(
+    results: &[ResponseStatistics],
+    precision: u32,
+) -> ResponsesTimings {
+    get_timings(results.iter().filter_map(|r| r.response_time), precision)
+}
+
+// Gets the minimum, average, maximum and percentile based timings from a set of response times, with the percentiles
+// estimated from a histogram of them in microseconds kept to the given number of significant figures.
+pub(crate) fn get_timings(
+    response_times: impl Iterator<Item = Duration>,
+    precision: u32,
+) -> ResponsesTimings {
+    let mut min = Duration::ZERO;
+    let mut max = Duration::ZERO;
+
+    // average and standard deviation
+    let mut count = 0;
+    let mut total = Duration::ZERO;
+    let mut total_squared_millis = 0.0;
+
+    // percentiles
+    let mut histogram = Histogram::configure()
+        .precision(precision)
+        .max_value(LATENCY_HISTOGRAM_MAX_MICROS)
+        .build()
+        .unwrap();
+
+    for response_time in response_times {
+        if count == 0 || response_time < min {
+            min = response_time
+        }
+
+        if response_time > max {
+            max = response_time
+        }
+
+        count += 1;
+        total += response_time;
+        total_squared_millis += (response_time.as_secs_f64() * 1000.0).powi(2);
+        histogram
+            .increment((response_time.as_micros() as u64).min(LATENCY_HISTOGRAM_MAX_MICROS))
+            .unwrap()
+    }
+
+    let avg = if count > 0 {
+        total / count
+    } else {
+        Duration::ZERO
+    };
+    let variance = if count > 0 {
+        total_squared_millis / count as f64 - (avg.as_secs_f64() * 1000.0).powi(2)
+    } else {
+        0.0
+    };
+
+    ResponsesTimings {
+        min,
+        avg,
+        max,
+        std_deviation: Duration::from_secs_f64(variance.max(0.0).sqrt() / 1000.0),
+        fiftieth_percentile: Duration::from_micros(histogram.percentile(50.0).unwrap_or(0)),
+        seventy_fifth_percentile: Duration::from_micros(histogram.percentile(75.0).unwrap_or(0)),
+        ninetieth_percentile: Duration::from_micros(histogram.percentile(90.0).unwrap_or(0)),
+        ninety_ninth_percentile: Duration::from_micros(histogram.percentile(99.0).unwrap_or(0)),
+    }
+}
+
+/// From a vector of response statistics generate an ordered hashmap grouping of the status codes in the response and
+/// their counts.
+pub(crate) fn get_ordered_status_code_counts_from_results(
+    results: &[ResponseStatistics],
+) -> HashMap<Option<StatusCode>, usize> {
+    let mut response: HashMap<Option<StatusCode>, usize> = HashMap::new();
+
+    for result in results {
+        *response.entry(result.status_code).or_insert(0) += 1;
+    }
+
+    response
+        .into_iter()
+        .sorted_by(|a, b| a.1.cmp(&b.1))
+        .collect()
+}
+
+/// From a vector of response statistics generate an ordered grouping of the edge header name and value pairs seen and
+/// their counts, grouped by header and most common first.
+pub(crate) fn get_ordered_edge_header_counts_from_results(
+    results: &[ResponseStatistics],
+) -> Vec<((&'static str, String), usize)> {
+    let mut response: HashMap<(&'static str, String), usize> = HashMap::new();
+
+    for result in results {
+        for edge_header in &result.edge_headers {
+            *response.entry(edge_header.clone()).or_insert(0) += 1;
+        }
+    }
+
+    response
+        .into_iter()
+        .sorted_by(|a, b| a.0 .0.cmp(b.0 .0).then(b.1.cmp(&a.1)))
+        .collect()
+}
+
+/// Represents the results of a load test: the statistics of every request that was sent, along with what happened
+/// during the run.
+pub struct TestResults {
+    pub(crate) results: Vec<ResponseStatistics>,
+    pub(crate) state: Arc<RunState>,
+    pub(crate) elapsed: Duration,
+    pub(crate) args: Arc<ParsedArgs>,
+}
+
+/// The results of a run, as [`LoadTestBuilder::run`](crate::LoadTestBuilder::run) gives them, which are the same as a
+/// [`LoadTest`](crate::LoadTest)'s [`TestResults`].
+pub type RunResults = TestResults;
+
+impl TestResults {
+    /// The number of requests that completed, whether they were successful or not.
+    pub fn requests(&self) -> usize {
+        self.results.len()
+    }
+
+    /// The number of requests that were successful.
+    pub fn successful(&self) -> usize {
+        self.results.iter().filter(|r| r.is_success).count()
+    }
+
+    /// The number of requests that failed.
+    pub fn failed(&self) -> usize {
+        self.requests() - self.successful()
+    }
+
+    /// The percentage of requests that failed.
+    pub fn error_rate(&self) -> f64 {
+        self.failed() as f64 / self.requests().max(1) as f64 * 100.0
+    }
+
+    /// How long the run took.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The average number of requests that completed each second of the run.
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// The timings of the responses, which leave out those sent whilst ramping up or down unless asked otherwise.
+    pub fn timings(&self) -> ResponsesTimings {
+        get_timings_from_results(
+            &self
+                .results
+                .iter()
+                .filter(|r| self.args.include_ramps || r.ramp.is_none())
+                .cloned()
+                .collect_vec(),
+            self.args.latency_precision,
+        )
+    }
+
+    /// Whether the response times and error rate were kept within the thresholds that the run was given (such as with
+    /// `--fail-if-p99` or [`LoadTestBuilder::latency_threshold`](crate::LoadTestBuilder::latency_threshold)), which
+    /// they always are if it wasn't given any.
+    pub fn thresholds_passed(&self) -> bool {
+        get_threshold_checks(&self.results, &self.args)
+            .iter()
+            .all(|(within, _)| *within)
+    }
+
+    /// Whether the run was stopped early by the cancellation token that it was built with.
+    pub fn cancelled(&self) -> bool {
+        self.state.stop_reason() == Some(StopReason::Cancelled)
+    }
+
+    /// The number of responses that had the given status code.
+    pub fn status_code_count(&self, status_code: u16) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.status_code.is_some_and(|s| s.as_u16() == status_code))
+            .count()
+    }
+}
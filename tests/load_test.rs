@@ -0,0 +1,319 @@
+//! Load tests run through smashit's library against a local server.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use futures::StreamExt;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use itertools::Itertools;
+use smashit::{
+    run_cli, CancellationToken, LatencyStatistic, LoadTest, LoadTestBuilder, Method, TestConfig,
+};
+
+/// Starts a server on a free port that responds to `/fail` with a 500 and to everything else with a 200, taking a
+/// second to respond to the first request to `/slow-first`, recording the bodies of the requests sent to it.
+fn start_server() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let bodies = Arc::new(Mutex::new(vec![]));
+    let recorded = bodies.clone();
+    let slow_requests = Arc::new(AtomicUsize::new(0));
+    let make_service = make_service_fn(move |_| {
+        let (bodies, slow_requests) = (bodies.clone(), slow_requests.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let (bodies, slow_requests) = (bodies.clone(), slow_requests.clone());
+                async move {
+                    let status = match request.uri().path() {
+                        "/fail" => StatusCode::INTERNAL_SERVER_ERROR,
+                        "/slow-first" => {
+                            if slow_requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                                tokio::time::sleep(Duration::from_secs(1)).await;
+                            }
+                            StatusCode::OK
+                        }
+                        _ => StatusCode::OK,
+                    };
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    bodies
+                        .lock()
+                        .unwrap()
+                        .push(String::from_utf8_lossy(&body).into_owned());
+
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .status(status)
+                            .body(Body::from("hello"))
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(server);
+    (address, recorded)
+}
+
+#[tokio::test]
+async fn load_test_from_args_sends_every_request() {
+    let (address, _) = start_server();
+    let config = TestConfig::from_args([
+        "-u",
+        &format!("http://{0}/ok", address),
+        "-c",
+        "20",
+        "-p",
+        "4",
+    ])
+    .unwrap();
+
+    let results = LoadTest::new(config).await.unwrap().run().await.unwrap();
+
+    assert_eq!(results.requests(), 20);
+    assert_eq!(results.successful(), 20);
+    assert_eq!(results.status_code_count(200), 20);
+    assert!(!results.cancelled());
+}
+
+#[tokio::test]
+async fn load_test_counts_unexpected_statuses_as_failures() {
+    let (address, _) = start_server();
+    let config =
+        TestConfig::from_args(["-u", &format!("http://{0}/fail", address), "-c", "5"]).unwrap();
+
+    let results = LoadTest::new(config).await.unwrap().run().await.unwrap();
+
+    assert_eq!(results.failed(), 5);
+    assert_eq!(results.status_code_count(500), 5);
+    assert_eq!(results.error_rate(), 100.0);
+}
+
+#[tokio::test]
+async fn hedged_requests_complete_once_the_hedge_wins() {
+    let (address, _) = start_server();
+    let config = TestConfig::from_args([
+        "-u",
+        &format!("http://{0}/slow-first", address),
+        "-c",
+        "3",
+        "--hedge-after",
+        "50ms",
+    ])
+    .unwrap();
+
+    let results = LoadTest::new(config).await.unwrap().run().await.unwrap();
+
+    assert_eq!(results.successful(), 3);
+    assert!(results.timings().max < Duration::from_millis(500));
+    assert!(results.elapsed() < Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn requests_that_arent_idempotent_are_only_retried_when_allowed_to_be() {
+    let (address, bodies) = start_server();
+    let url = format!("http://{0}/fail", address);
+    let retried_args = ["--retries", "2", "--retry-on", "500", "-c", "1"];
+
+    let config =
+        TestConfig::from_args(["-u", &url, "-m", "POST"].into_iter().chain(retried_args)).unwrap();
+    LoadTest::new(config).await.unwrap().run().await.unwrap();
+    assert_eq!(bodies.lock().unwrap().len(), 1);
+
+    let config = TestConfig::from_args(
+        ["-u", &url, "-m", "POST", "--retry-non-idempotent"]
+            .into_iter()
+            .chain(retried_args),
+    )
+    .unwrap();
+    LoadTest::new(config).await.unwrap().run().await.unwrap();
+    assert_eq!(bodies.lock().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn requests_are_retried_on_the_statuses_given_backing_off_between_retries() {
+    let (address, bodies) = start_server();
+    let config = TestConfig::from_args([
+        "-u",
+        &format!("http://{0}/fail", address),
+        "-c",
+        "1",
+        "--retries",
+        "2",
+        "--retry-on",
+        "500",
+        "--retry-backoff",
+        "100ms",
+    ])
+    .unwrap();
+
+    let results = LoadTest::new(config).await.unwrap().run().await.unwrap();
+
+    assert_eq!(bodies.lock().unwrap().len(), 3);
+    assert_eq!(results.failed(), 1);
+    assert!(results.elapsed() >= Duration::from_millis(300));
+
+    let config = TestConfig::from_args([
+        "-u",
+        &format!("http://{0}/fail", address),
+        "-c",
+        "1",
+        "--retries",
+        "2",
+    ])
+    .unwrap();
+    LoadTest::new(config).await.unwrap().run().await.unwrap();
+    assert_eq!(bodies.lock().unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn warm_up_requests_take_their_own_places_in_the_sequence() {
+    let (address, bodies) = start_server();
+    let config = TestConfig::from_args([
+        "-u",
+        &format!("http://{0}/users", address),
+        "-m",
+        "POST",
+        "-b",
+        "{{seq}}",
+        "-c",
+        "5",
+        "--warmup-requests",
+        "3",
+    ])
+    .unwrap();
+
+    LoadTest::new(config).await.unwrap().run().await.unwrap();
+
+    let bodies = bodies.lock().unwrap().clone();
+    assert_eq!(bodies.len(), 8);
+    assert_eq!(bodies.iter().unique().count(), 8);
+}
+
+#[tokio::test]
+async fn cli_run_fails_when_its_results_cant_be_saved() {
+    let (address, _) = start_server();
+    let args = [
+        "smashit",
+        "-u",
+        &format!("http://{0}/ok", address),
+        "-c",
+        "1",
+        "--save",
+        "/nonexistent/run.json",
+    ]
+    .map(String::from);
+
+    assert_eq!(run_cli(args.to_vec()).await, ExitCode::from(3));
+}
+
+#[tokio::test]
+async fn cli_run_saves_its_results_without_their_secrets() {
+    let (address, _) = start_server();
+    let path = std::env::temp_dir().join(format!("smashit-{0}-saved-run.json", std::process::id()));
+    let args = [
+        "smashit",
+        "-u",
+        &format!("http://{0}/ok?api_key=s3cret", address),
+        "-h",
+        "Authorization=Bearer s3cret",
+        "-c",
+        "2",
+        "--save",
+        &path.to_string_lossy(),
+    ]
+    .map(String::from);
+
+    assert_eq!(run_cli(args.to_vec()).await, ExitCode::SUCCESS);
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(saved.contains("api_key=redacted"));
+    assert!(!saved.contains("s3cret"));
+}
+
+#[tokio::test]
+async fn builder_sends_typed_options_and_streams_every_request() {
+    let (address, bodies) = start_server();
+    let mut builder = LoadTestBuilder::new()
+        .url(format!("http://{0}/users", address))
+        .method(Method::POST)
+        .header("Content-Type", "application/json")
+        .body(r#"{"id": {{seq}}}"#)
+        .count(10)
+        .concurrency(2);
+    let events = builder.events();
+
+    let results = builder.run().await.unwrap();
+    let events = events.collect::<Vec<_>>().await;
+
+    assert_eq!(results.successful(), 10);
+    assert_eq!(events.len(), 10);
+    assert!(events
+        .iter()
+        .all(|e| e.method == "POST" && e.status == Some(200) && e.success));
+    let mut bodies = bodies.lock().unwrap().clone();
+    bodies.sort_by_key(|b| b.len());
+    assert_eq!(bodies.first().map(String::as_str), Some(r#"{"id": 1}"#));
+    assert_eq!(bodies.last().map(String::as_str), Some(r#"{"id": 10}"#));
+}
+
+#[tokio::test]
+async fn builder_checks_the_results_against_its_thresholds() {
+    let (address, _) = start_server();
+    let builder = |path: &str| {
+        LoadTestBuilder::new()
+            .url(format!("http://{0}/{1}", address, path))
+            .count(5)
+            .retries(1)
+            .latency_threshold(LatencyStatistic::P99, Duration::from_secs(5))
+            .max_error_rate(0.0)
+    };
+
+    let results = builder("ok").run().await.unwrap();
+    assert!(results.thresholds_passed());
+
+    let results = builder("fail").run().await.unwrap();
+    assert!(!results.thresholds_passed());
+
+    let results = builder("fail").expect_status(500).run().await.unwrap();
+    assert!(results.thresholds_passed());
+}
+
+#[tokio::test]
+async fn builder_reports_progress_and_stops_once_cancelled() {
+    let (address, _) = start_server();
+    let token = CancellationToken::new();
+    let progress_calls = Arc::new(AtomicUsize::new(0));
+    let calls = progress_calls.clone();
+    let builder = LoadTestBuilder::new()
+        .url(format!("http://{0}/ok", address))
+        .rate(20.0)
+        .duration(Duration::from_secs(60))
+        .on_progress(Duration::from_millis(50), move |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+        })
+        .cancellation_token(token.clone());
+
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        token.cancel();
+    });
+    let results = tokio::time::timeout(Duration::from_secs(15), builder.run())
+        .await
+        .expect("the run stopped once it was cancelled")
+        .unwrap();
+
+    assert!(results.cancelled());
+    assert!(results.requests() > 0);
+    assert!(progress_calls.load(Ordering::SeqCst) > 0);
+}